@@ -0,0 +1,302 @@
+//! Marking up a rendered image with where it is on the complex plane: the
+//! axes, a labeled coordinate grid, a scale bar and a caption giving the
+//! viewport.
+//!
+//! Everything is drawn straight onto the grayscale pixels, in black or white
+//! depending on what lies underneath, with a small built-in bitmap font so
+//! that no system fonts are needed.
+
+use crate::point_to_pixel;
+use num::Complex;
+
+/// Glyphs are five pixels wide and seven tall, one row per byte with the
+/// leftmost pixel in the highest of the five low bits.
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+/// The characters the font has. Anything else is drawn as a hollow box.
+const FONT: &[(char, [u8; GLYPH_HEIGHT])] = &[
+    ('0', [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e]),
+    ('1', [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e]),
+    ('2', [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f]),
+    ('3', [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e]),
+    ('4', [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02]),
+    ('5', [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e]),
+    ('6', [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e]),
+    ('7', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e]),
+    ('9', [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c]),
+    ('-', [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1f, 0x04, 0x04, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0c, 0x04, 0x08]),
+    ('i', [0x04, 0x00, 0x0c, 0x04, 0x04, 0x04, 0x0e]),
+    ('o', [0x00, 0x00, 0x0e, 0x11, 0x11, 0x11, 0x0e]),
+    ('t', [0x08, 0x08, 0x1c, 0x08, 0x08, 0x09, 0x06]),
+    ('x', [0x00, 0x00, 0x11, 0x0a, 0x04, 0x0a, 0x11]),
+    (' ', [0x00; GLYPH_HEIGHT]),
+];
+
+const MISSING_GLYPH: [u8; GLYPH_HEIGHT] = [0x1f, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1f];
+
+fn glyph(c: char) -> &'static [u8; GLYPH_HEIGHT] {
+    FONT.iter()
+        .find(|&&(f, _)| f == c)
+        .map(|(_, rows)| rows)
+        .unwrap_or(&MISSING_GLYPH)
+}
+
+/// Aim for about this many grid lines across the image.
+const GRID_LINES: f64 = 6.0;
+
+/// A round number, one, two or five times a power of ten, that divides
+/// `span` into about `lines` pieces.
+fn nice_step(span: f64, lines: f64) -> f64 {
+    let raw = span / lines;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .find(|&m| m * magnitude >= raw)
+        .unwrap_or(10.0);
+    step * magnitude
+}
+
+/// Format `value`, a multiple of `step`, with just enough decimals to tell
+/// neighbouring multiples apart.
+fn format_multiple(value: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let text = format!("{:.*}", decimals, value);
+    // Don't label the origin "-0".
+    if value.abs() < step / 2.0 {
+        return format!("{:.*}", decimals, 0.0);
+    }
+    text
+}
+
+/// A grayscale image being annotated.
+struct Canvas<'a> {
+    pixels: &'a mut [u8],
+    bounds: (usize, usize),
+    /// The size of a font pixel in image pixels.
+    scale: usize,
+}
+
+impl Canvas<'_> {
+    /// A value that stands out against the pixel at (`col`, `row`).
+    fn contrast(&self, col: usize, row: usize) -> u8 {
+        if self.pixels[row * self.bounds.0 + col] < 128 {
+            255
+        } else {
+            0
+        }
+    }
+
+    fn set(&mut self, col: isize, row: isize, value: u8) {
+        if col >= 0 && row >= 0 && (col as usize) < self.bounds.0 && (row as usize) < self.bounds.1
+        {
+            self.pixels[row as usize * self.bounds.0 + col as usize] = value;
+        }
+    }
+
+    /// Draw a vertical line at `col`, dotted if `dotted` is true.
+    fn vertical(&mut self, col: usize, dotted: bool) {
+        for row in 0..self.bounds.1 {
+            if !dotted || row % 4 < 2 {
+                let value = self.contrast(col, row);
+                self.set(col as isize, row as isize, value);
+            }
+        }
+    }
+
+    /// Draw a horizontal line at `row`, dotted if `dotted` is true.
+    fn horizontal(&mut self, row: usize, dotted: bool) {
+        for col in 0..self.bounds.0 {
+            if !dotted || col % 4 < 2 {
+                let value = self.contrast(col, row);
+                self.set(col as isize, row as isize, value);
+            }
+        }
+    }
+
+    /// The width and height `text` takes up when drawn.
+    fn text_size(&self, text: &str) -> (usize, usize) {
+        let chars = text.chars().count();
+        (
+            (chars * (GLYPH_WIDTH + 1)).saturating_sub(1) * self.scale,
+            GLYPH_HEIGHT * self.scale,
+        )
+    }
+
+    /// Draw `text` in white outlined in black, with its upper left corner at
+    /// (`left`, `top`), so that it reads against any background.
+    fn text(&mut self, left: isize, top: isize, text: &str) {
+        for (value, offsets) in [(0, &[-1, 0, 1][..]), (255, &[0][..])] {
+            for (i, c) in text.chars().enumerate() {
+                let x0 = left + (i * (GLYPH_WIDTH + 1) * self.scale) as isize;
+                for (y, bits) in glyph(c).iter().enumerate() {
+                    for x in 0..GLYPH_WIDTH {
+                        if bits & (0x10 >> x) == 0 {
+                            continue;
+                        }
+                        for sy in 0..self.scale {
+                            for sx in 0..self.scale {
+                                let col = x0 + (x * self.scale + sx) as isize;
+                                let row = top + (y * self.scale + sy) as isize;
+                                for &dy in offsets {
+                                    for &dx in offsets {
+                                        self.set(col + dx, row + dy, value);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draw the axes, a labeled coordinate grid, a scale bar and a caption onto
+/// `pixels`, an image from `render` with dimensions `bounds` covering the
+/// rectangle from `upper_left` to `lower_right`.
+pub fn annotate(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    let mut canvas = Canvas {
+        pixels,
+        bounds,
+        scale: (bounds.0 / 500).max(1),
+    };
+    let margin = 4 * canvas.scale as isize;
+    let (width, height) = (
+        lower_right.re - upper_left.re,
+        upper_left.im - lower_right.im,
+    );
+    let middle = Complex::new(upper_left.re + width / 2.0, lower_right.im + height / 2.0);
+    let step = nice_step(width.max(height), GRID_LINES);
+    let column =
+        |re: f64| point_to_pixel(bounds, Complex::new(re, middle.im), upper_left, lower_right);
+    let row =
+        |im: f64| point_to_pixel(bounds, Complex::new(middle.re, im), upper_left, lower_right);
+
+    // Grid lines at multiples of `step`, with the axes drawn solid.
+    let mut labels = Vec::new();
+    let first = (upper_left.re / step).ceil() as i64;
+    let last = (lower_right.re / step).floor() as i64;
+    for k in first..=last {
+        let re = k as f64 * step;
+        if let Some((col, _)) = column(re) {
+            canvas.vertical(col, k != 0);
+            labels.push((col, format_multiple(re, step)));
+        }
+    }
+    // Label along the top, skipping any label that would run into the last.
+    let mut clear_from = 0;
+    for (col, label) in labels.drain(..) {
+        let (w, _) = canvas.text_size(&label);
+        let left = col as isize + canvas.scale as isize * 2;
+        if left >= clear_from && left + (w as isize) < bounds.0 as isize {
+            canvas.text(left, margin, &label);
+            clear_from = left + w as isize + margin;
+        }
+    }
+
+    let first = (lower_right.im / step).ceil() as i64;
+    let last = (upper_left.im / step).floor() as i64;
+    for k in first..=last {
+        let im = k as f64 * step;
+        if let Some((_, row)) = row(im) {
+            canvas.horizontal(row, k != 0);
+            labels.push((row, format_multiple(im, step) + "i"));
+        }
+    }
+    // Label down the left side, below the labels along the top.
+    let (_, text_height) = canvas.text_size("0");
+    let mut clear_from = 2 * margin + text_height as isize;
+    for (row, label) in labels.into_iter().rev() {
+        let top = row as isize - (text_height + 2 * canvas.scale) as isize;
+        if top >= clear_from {
+            canvas.text(margin, top, &label);
+            clear_from = top + text_height as isize + margin;
+        }
+    }
+
+    // A scale bar in the lower right corner, about a fifth of the width.
+    let bar = nice_step(width, 5.0);
+    let bar_length = (bar / width * bounds.0 as f64).round() as isize;
+    let bar_right = bounds.0 as isize - margin;
+    let bar_row = bounds.1 as isize - margin;
+    for y in 0..2 * canvas.scale as isize {
+        for x in bar_right - bar_length..bar_right {
+            canvas.set(x, bar_row - y, 255);
+        }
+        canvas.set(bar_right - bar_length - 1, bar_row - y, 0);
+        canvas.set(bar_right, bar_row - y, 0);
+    }
+    let bar_label = format_multiple(bar, bar);
+    let (w, h) = canvas.text_size(&bar_label);
+    canvas.text(
+        bar_right - bar_length / 2 - w as isize / 2,
+        bar_row - 2 * canvas.scale as isize - margin / 2 - h as isize,
+        &bar_label,
+    );
+
+    // The caption in the lower left corner.
+    let caption = format!(
+        "x {} to {}  {}i to {}i  {}x{}",
+        upper_left.re, lower_right.re, lower_right.im, upper_left.im, bounds.0, bounds.1
+    );
+    let (_, h) = canvas.text_size(&caption);
+    canvas.text(margin, bounds.1 as isize - margin - h as isize, &caption);
+}
+
+#[test]
+fn test_nice_step() {
+    assert_eq!(nice_step(3.0, 6.0), 0.5);
+    assert_eq!(nice_step(0.2, 5.0), 0.05);
+    assert_eq!(nice_step(10.0, 6.0), 2.0);
+    assert_eq!(nice_step(1e-9, 5.0), 2e-10);
+}
+
+#[test]
+fn test_format_multiple() {
+    assert_eq!(format_multiple(-1.5, 0.5), "-1.5");
+    assert_eq!(format_multiple(2.0, 1.0), "2");
+    assert_eq!(format_multiple(-1e-17, 0.05), "0.00");
+    assert_eq!(format_multiple(0.35, 0.05), "0.35");
+}
+
+#[test]
+fn test_font_covers_labels() {
+    for c in "0123456789-+.,i ox".chars() {
+        assert_ne!(glyph(c), &MISSING_GLYPH, "{:?}", c);
+    }
+    assert_eq!(glyph('Q'), &MISSING_GLYPH);
+}
+
+#[test]
+fn test_annotate() {
+    let bounds = (300, 200);
+    let upper_left = Complex::new(-2.0, 1.0);
+    let lower_right = Complex::new(1.0, -1.0);
+    let mut pixels = vec![128; bounds.0 * bounds.1];
+    annotate(&mut pixels, bounds, upper_left, lower_right);
+
+    // The imaginary axis is solid, at the column for zero.
+    let (axis, _) =
+        point_to_pixel(bounds, Complex::new(0.0, 0.0), upper_left, lower_right).unwrap();
+    assert_eq!(axis, 200);
+    let axis_pixels = (0..bounds.1).filter(|row| pixels[row * bounds.0 + axis] != 128);
+    assert!(axis_pixels.count() > bounds.1 * 3 / 4);
+
+    // Grid lines at -1.5 are dotted.
+    let (grid, _) =
+        point_to_pixel(bounds, Complex::new(-1.5, 0.0), upper_left, lower_right).unwrap();
+    let grid_pixels = (0..bounds.1).filter(|row| pixels[row * bounds.0 + grid] != 128);
+    let count = grid_pixels.count();
+    assert!(count >= bounds.1 / 2 && count < bounds.1 * 3 / 4);
+}
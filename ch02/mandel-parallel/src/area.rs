@@ -0,0 +1,305 @@
+//! Estimating the area of the Mandelbrot set.
+//!
+//! The upper half of the set is covered with a grid of square cells, and
+//! each cell is classified by sampling `escape_time` around its edges and
+//! across it. A cell whose edges are all inside is counted as wholly inside:
+//! the set has no holes, so nothing within a closed curve of its points can
+//! escape. A cell whose samples all escape is counted as outside, which
+//! misses any speck of the set small enough to fall between the samples. A
+//! mixed cell is split into four and tried again, down to a fixed depth;
+//! the cells left on the boundary at that depth are measured by Monte Carlo
+//! sampling, which is where the statistical error comes from.
+//!
+//! A point counts as inside if it hasn't escaped within the iteration limit,
+//! so the estimate comes down towards the true area as the limit rises.
+
+use crate::explore::Rng;
+use crate::{escape_time, parsed, take_option, DEFAULT_LIMIT, EXIT_USAGE};
+use num::Complex;
+use std::ops::Add;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// The region measured: the upper half of the plane as far as the set
+/// reaches, which is all of it that matters, since the set is symmetric
+/// about the real axis. Its width is twice its height.
+const REGION_LEFT: f64 = -2.0;
+const REGION_WIDTH: f64 = 2.5;
+const REGION_HEIGHT: f64 = 1.25;
+
+/// How many cells the region is divided into across; there are half as many
+/// down.
+const GRID: usize = 32;
+
+/// How many points along each edge, and across each row inside, a cell is
+/// sampled at before it is trusted to be all inside or all outside.
+const PROBES: usize = 8;
+
+/// The published estimate of the area from Förstemann's pixel counting,
+/// for comparison: 1.5065918849 ± 0.0000000028.
+pub const REFERENCE_AREA: f64 = 1.5065918849;
+
+/// How finely to measure the set.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub limit: usize,
+    /// How many times a cell on the boundary of the set may be split.
+    pub depth: usize,
+    /// Random points sampled in each cell left on the boundary.
+    pub samples: usize,
+    pub seed: u64,
+}
+
+/// A running total of area and the variance of its error.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Estimate {
+    pub area: f64,
+    pub variance: f64,
+    /// How many cells were measured by sampling.
+    pub boundary_cells: usize,
+}
+
+impl Add for Estimate {
+    type Output = Estimate;
+
+    fn add(self, other: Estimate) -> Estimate {
+        Estimate {
+            area: self.area + other.area,
+            variance: self.variance + other.variance,
+            boundary_cells: self.boundary_cells + other.boundary_cells,
+        }
+    }
+}
+
+impl Estimate {
+    /// Half the width of a 95% confidence interval for the area. This only
+    /// covers the sampling error, not anything the classification missed.
+    pub fn error(&self) -> f64 {
+        2.0 * self.variance.sqrt()
+    }
+}
+
+fn inside(c: Complex<f64>, limit: usize) -> bool {
+    escape_time(c, limit).is_none()
+}
+
+/// Whether the square cell with lower left corner `corner` and side `size`
+/// is wholly inside (`Some(true)`), wholly outside (`Some(false)`), or
+/// neither, as far as `PROBES` samples across it can tell.
+fn classify(corner: Complex<f64>, size: f64, limit: usize) -> Option<bool> {
+    let step = size / PROBES as f64;
+    let at = |i: usize, j: usize| corner + Complex::new(i as f64 * step, j as f64 * step);
+
+    let mut edges = (0..PROBES).flat_map(|k| {
+        [
+            at(k, 0),
+            at(PROBES, k),
+            at(PROBES - k, PROBES),
+            at(0, PROBES - k),
+        ]
+    });
+    let first = inside(at(0, 0), limit);
+    if !edges.all(|c| inside(c, limit) == first) {
+        return None;
+    }
+    if first {
+        return Some(true);
+    }
+    // Edges all outside could still enclose part of the set.
+    let mut interior = (1..PROBES).flat_map(|i| (1..PROBES).map(move |j| (i, j)));
+    if interior.any(|(i, j)| inside(at(i, j), limit)) {
+        None
+    } else {
+        Some(false)
+    }
+}
+
+/// Measure the part of the set in the square cell with lower left corner
+/// `corner` and side `size`, splitting it up to `depth` more times.
+fn measure_cell(
+    corner: Complex<f64>,
+    size: f64,
+    depth: usize,
+    settings: &Settings,
+    rng: &mut Rng,
+) -> Estimate {
+    let cell_area = size * size;
+    match classify(corner, size, settings.limit) {
+        Some(true) => {
+            return Estimate {
+                area: cell_area,
+                ..Estimate::default()
+            }
+        }
+        Some(false) => return Estimate::default(),
+        None => {}
+    }
+
+    if depth > 0 {
+        let half = size / 2.0;
+        return [(0.0, 0.0), (half, 0.0), (0.0, half), (half, half)]
+            .iter()
+            .map(|&(x, y)| {
+                let corner = corner + Complex::new(x, y);
+                measure_cell(corner, half, depth - 1, settings, rng)
+            })
+            .fold(Estimate::default(), Add::add);
+    }
+
+    let hits = (0..settings.samples)
+        .filter(|_| {
+            let offset = Complex::new(rng.next_f64() * size, rng.next_f64() * size);
+            inside(corner + offset, settings.limit)
+        })
+        .count();
+    let n = settings.samples as f64;
+    let fraction = hits as f64 / n;
+    Estimate {
+        area: cell_area * fraction,
+        variance: cell_area * cell_area * fraction * (1.0 - fraction) / n,
+        boundary_cells: 1,
+    }
+}
+
+/// Estimate the area of the Mandelbrot set, measuring the top-level cells
+/// in parallel.
+///
+/// Each top-level cell draws its samples from its own generator, seeded
+/// from `settings.seed` and the cell's position, so the estimate doesn't
+/// depend on which thread measures which cell.
+pub fn estimate_area(settings: &Settings) -> Estimate {
+    let size = REGION_WIDTH / GRID as f64;
+    let rows = (REGION_HEIGHT / size).round() as usize;
+    let cells = GRID * rows;
+    let next = AtomicUsize::new(0);
+    let measured = Mutex::new(vec![Estimate::default(); cells]);
+
+    crossbeam::scope(|spawner| {
+        for _ in 0..num_cpus::get() {
+            spawner.spawn(|_| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= cells {
+                    break;
+                }
+                let corner = Complex::new(
+                    REGION_LEFT + (index % GRID) as f64 * size,
+                    (index / GRID) as f64 * size,
+                );
+                let mut rng = Rng::new(settings.seed ^ (index as u64).wrapping_mul(0x9e37_79b9));
+                let estimate = measure_cell(corner, size, settings.depth, settings, &mut rng);
+                measured.lock().unwrap()[index] = estimate;
+            });
+        }
+    })
+    .unwrap();
+
+    // Add the cells up in order, so rounding comes out the same too.
+    let half = measured
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .fold(Estimate::default(), Add::add);
+    // Count the lower half too.
+    Estimate {
+        area: 2.0 * half.area,
+        variance: 4.0 * half.variance,
+        boundary_cells: 2 * half.boundary_cells,
+    }
+}
+
+/// Entry point for
+/// `mandelbrot area [--iterations N] [--depth N] [--samples N] [--seed N]`.
+///
+/// Estimates the area at iteration limits doubling from 64 up to the one
+/// given, and prints how the estimate converges.
+pub fn area_main(args: &[String]) {
+    let mut args = args.to_vec();
+    let parse = |args: &mut Vec<String>, option: &str, default: usize| {
+        take_option(args, option)
+            .map(|s| parsed(usize::from_str(&s), option))
+            .unwrap_or(default)
+    };
+    let limit = parse(&mut args, "--iterations", 4 * (DEFAULT_LIMIT + 1));
+    let depth = parse(&mut args, "--depth", 5);
+    let samples = parse(&mut args, "--samples", 64);
+    let seed = parse(&mut args, "--seed", 1) as u64;
+
+    if args.len() != 2 || limit < 64 || samples == 0 {
+        eprintln!(
+            "Usage: {} area [--iterations N] [--depth N] [--samples N] [--seed N]",
+            args[0]
+        );
+        eprintln!("Example: {} area --iterations 4096 --depth 6", args[0]);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    println!(
+        "{:>8}  {:>12}  {:>10}  {:>11}  {:>10}  {:>8}",
+        "limit", "area", "± (95%)", "change", "boundary", "time"
+    );
+    let mut previous: Option<f64> = None;
+    let mut current = 64;
+    loop {
+        let start = Instant::now();
+        let estimate = estimate_area(&Settings {
+            limit: current,
+            depth,
+            samples,
+            seed,
+        });
+        let change = previous
+            .map(|p| format!("{:+.7}", estimate.area - p))
+            .unwrap_or_default();
+        println!(
+            "{:>8}  {:>12.7}  {:>10.7}  {:>11}  {:>10}  {:>8.2?}",
+            current,
+            estimate.area,
+            estimate.error(),
+            change,
+            estimate.boundary_cells,
+            start.elapsed()
+        );
+        previous = Some(estimate.area);
+        if current >= limit {
+            break;
+        }
+        current = (current * 2).min(limit);
+    }
+    if let Some(area) = previous {
+        println!(
+            "reference area {:.10}; the last estimate is off by {:+.7}",
+            REFERENCE_AREA,
+            area - REFERENCE_AREA
+        );
+    }
+}
+
+#[test]
+fn test_classify() {
+    // Well inside the main cardioid, well outside the set, and across the
+    // boundary at the cusp.
+    assert_eq!(classify(Complex::new(-0.2, -0.1), 0.2, 255), Some(true));
+    assert_eq!(classify(Complex::new(0.6, 0.6), 0.2, 255), Some(false));
+    assert_eq!(classify(Complex::new(0.15, -0.1), 0.2, 255), None);
+}
+
+#[test]
+fn test_estimate_area() {
+    let settings = Settings {
+        limit: 256,
+        depth: 2,
+        samples: 32,
+        seed: 7,
+    };
+    let estimate = estimate_area(&settings);
+    // Coarse, but it should be close, and a little high with so low a limit.
+    assert!(estimate.area > REFERENCE_AREA - 0.02, "{:?}", estimate);
+    assert!(estimate.area < REFERENCE_AREA + 0.05, "{:?}", estimate);
+    assert!(estimate.error() > 0.0 && estimate.error() < 0.02);
+    assert!(estimate.boundary_cells > 0);
+
+    // The same seed gives the same answer, however the cells are shared out.
+    assert_eq!(estimate_area(&settings), estimate);
+}
@@ -0,0 +1,208 @@
+//! A Julia atlas: a grid of small Julia sets, one for each of a grid of
+//! points `c` across a view of the Mandelbrot set, laid out where those
+//! points are.
+//!
+//! Connected Julia sets come from inside the Mandelbrot set and dust from
+//! outside it, and the most intricate ones from near its boundary, which
+//! the atlas shows at a glance.
+
+use crate::{
+    gray_level, invalid, io_or_exit, parse, parsed, parsed_view, pixel_to_point, render_bands,
+    write_image, BandHooks, DEFAULT_LIMIT, EXIT_USAGE,
+};
+use num::{Complex, Float};
+
+/// The width of the square of the plane each Julia set is drawn over,
+/// widened to suit the shape of the cells.
+const JULIA_WIDTH: f64 = 3.2;
+
+/// The gray level of the lines between cells.
+const GUTTER: u8 = 128;
+
+/// Determines whether `z` escapes to infinity within `limit` iterations of
+/// `z = z * z + c`.
+fn julia_escape_time<T: Float>(mut z: Complex<T>, c: Complex<T>, limit: usize) -> Option<usize> {
+    let four = T::from(4.0).unwrap();
+    for i in 0..limit {
+        if z.norm_sqr() > four {
+            return Some(i);
+        }
+        z = z * z + c;
+    }
+    None
+}
+
+/// Render the Julia set for `c` into `pixels`, a buffer with dimensions
+/// `bounds`, centered on the origin and `JULIA_WIDTH` wide or high,
+/// whichever is smaller.
+fn render_julia(pixels: &mut [u8], bounds: (usize, usize), c: Complex<f64>, limit: usize) {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    let unit = JULIA_WIDTH / bounds.0.min(bounds.1) as f64;
+    let half = Complex::new(bounds.0 as f64 * unit / 2.0, bounds.1 as f64 * unit / 2.0);
+    let upper_left = Complex::new(-half.re, half.im);
+    let lower_right = Complex::new(half.re, -half.im);
+
+    for row in 0..bounds.1 {
+        for col in 0..bounds.0 {
+            let z = pixel_to_point(bounds, (col, row), upper_left, lower_right);
+            pixels[row * bounds.0 + col] = gray_level(julia_escape_time(z, c, limit), limit);
+        }
+    }
+}
+
+/// The parameter `c` at the middle of cell (`x`, `y`) of a `grid` laid over
+/// the rectangle from `upper_left` to `lower_right`.
+fn cell_parameter(
+    grid: (usize, usize),
+    cell: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Complex<f64> {
+    // The middle of a cell is a corner in a grid twice as fine.
+    pixel_to_point(
+        (2 * grid.0, 2 * grid.1),
+        (2 * cell.0 + 1, 2 * cell.1 + 1),
+        upper_left,
+        lower_right,
+    )
+}
+
+/// Render an atlas of a `grid` of Julia sets, each `cell` pixels in size,
+/// for parameters across the rectangle from `upper_left` to `lower_right`.
+///
+/// Each row of cells is one band for `render_bands`, so the rows render in
+/// parallel.
+pub fn render_atlas(
+    grid: (usize, usize),
+    cell: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    limit: usize,
+) -> Vec<u8> {
+    let bounds = (grid.0 * cell.0, grid.1 * cell.1);
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        cell.1,
+        &BandHooks::default(),
+        |band, band_bounds, band_upper_left, band_lower_right| {
+            let mut julia = vec![0; cell.0 * cell.1];
+            for x in 0..grid.0 {
+                let c = cell_parameter((grid.0, 1), (x, 0), band_upper_left, band_lower_right);
+                render_julia(&mut julia, cell, c, limit);
+                for (row, line) in julia.chunks(cell.0).enumerate() {
+                    let start = row * band_bounds.0 + x * cell.0;
+                    band[start..start + cell.0].copy_from_slice(line);
+                }
+            }
+            // Rule off the right and bottom edges of every cell.
+            for row in 0..band_bounds.1 {
+                for x in 0..grid.0 {
+                    band[row * band_bounds.0 + (x + 1) * cell.0 - 1] = GUTTER;
+                }
+            }
+            let last = (band_bounds.1 - 1) * band_bounds.0;
+            band[last..].fill(GUTTER);
+        },
+    );
+    pixels
+}
+
+/// Entry point for `mandelbrot atlas FILE GRID CELL UPPERLEFT LOWERRIGHT`.
+pub fn atlas_main(args: &[String]) {
+    if args.len() != 7 {
+        eprintln!(
+            "Usage: {} atlas FILE GRID CELL UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} atlas atlas.png 10x8 120x120 -2.2,1.2 0.8,-1.2",
+            args[0]
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let grid: (usize, usize) = parsed(parse::parse_pair(&args[3], 'x'), "grid dimensions");
+    let cell: (usize, usize) = parsed(parse::parse_pair(&args[4], 'x'), "cell dimensions");
+    if grid.0 == 0 || grid.1 == 0 || cell.0 < 2 || cell.1 < 2 {
+        invalid("the grid must have at least one cell, and cells at least 2x2 pixels");
+    }
+    let bounds = match (grid.0.checked_mul(cell.0), grid.1.checked_mul(cell.1)) {
+        (Some(width), Some(height))
+            if width
+                .checked_mul(height)
+                .is_some_and(|pixels| pixels <= parse::MAX_PIXELS) =>
+        {
+            (width, height)
+        }
+        _ => invalid(&format!(
+            "the atlas must be at most {} pixels in all",
+            parse::MAX_PIXELS
+        )),
+    };
+    let (upper_left, lower_right) = parsed_view(&[&args[5], &args[6]], grid);
+
+    let pixels = render_atlas(grid, cell, upper_left, lower_right, DEFAULT_LIMIT);
+    io_or_exit(
+        write_image(&args[2], &pixels, bounds, None),
+        "writing PNG file",
+    );
+}
+
+#[test]
+fn test_julia_escape_time() {
+    // For c = 0 the Julia set is the unit circle.
+    let zero = Complex::new(0.0, 0.0);
+    assert_eq!(julia_escape_time(Complex::new(0.5, 0.5), zero, 255), None);
+    assert_eq!(
+        julia_escape_time(Complex::new(1.5, 0.0), zero, 255),
+        Some(1)
+    );
+    assert_eq!(
+        julia_escape_time(Complex::new(3.0, 0.0), zero, 255),
+        Some(0)
+    );
+}
+
+#[test]
+fn test_cell_parameter() {
+    let upper_left = Complex::new(-2.0, 1.0);
+    let lower_right = Complex::new(2.0, -1.0);
+    assert_eq!(
+        cell_parameter((2, 2), (0, 0), upper_left, lower_right),
+        Complex::new(-1.0, 0.5)
+    );
+    assert_eq!(
+        cell_parameter((4, 1), (3, 0), upper_left, lower_right),
+        Complex::new(1.5, 0.0)
+    );
+}
+
+#[test]
+fn test_render_atlas() {
+    let (grid, cell) = ((3, 2), (16, 12));
+    let upper_left = Complex::new(-1.5, 1.0);
+    let lower_right = Complex::new(1.5, -1.0);
+    let atlas = render_atlas(grid, cell, upper_left, lower_right, 64);
+    let width = grid.0 * cell.0;
+    assert_eq!(atlas.len(), width * grid.1 * cell.1);
+
+    // Every cell holds the Julia set for its own parameter.
+    for y in 0..grid.1 {
+        for x in 0..grid.0 {
+            let c = cell_parameter(grid, (x, y), upper_left, lower_right);
+            let mut julia = vec![0; cell.0 * cell.1];
+            render_julia(&mut julia, cell, c, 64);
+            for row in 0..cell.1 - 1 {
+                let start = (y * cell.1 + row) * width + x * cell.0;
+                assert_eq!(
+                    atlas[start..start + cell.0 - 1],
+                    julia[row * cell.0..(row + 1) * cell.0 - 1]
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Benchmarking: rendering a fixed set of views with more and more threads,
+//! to see how well rendering scales on a machine.
+//!
+//! Each view is rendered several times at each thread count, from one
+//! thread up to the number of logical CPUs, taking in the number of
+//! physical cores along the way. The views are chosen to load the threads
+//! differently: the whole set has large areas that are cheap and a band
+//! through the middle that is expensive, while the zoomed views cost much
+//! the same everywhere.
+
+use crate::parse::parse_size;
+use crate::parse::viewport;
+use crate::{parsed, render, render_bands, take_flag, take_option, BandHooks, EXIT_USAGE};
+use num::Complex;
+use std::str::FromStr;
+use std::time::Instant;
+
+/// A standard view to render.
+struct View {
+    name: &'static str,
+    center: (f64, f64),
+    width: f64,
+    limit: usize,
+}
+
+const VIEWS: [View; 4] = [
+    View {
+        name: "whole set",
+        center: (-0.5, 0.0),
+        width: 3.5,
+        limit: 255,
+    },
+    View {
+        name: "seahorse valley",
+        center: (-0.7436447, 0.1318252),
+        width: 0.01,
+        limit: 1000,
+    },
+    View {
+        name: "elephant valley",
+        center: (0.2925, 0.0149),
+        width: 0.02,
+        limit: 1000,
+    },
+    View {
+        name: "deep spiral",
+        center: (-0.7436438870371587, 0.1318259042053119),
+        width: 1e-9,
+        limit: 4000,
+    },
+];
+
+/// The thread counts to try: powers of two up to `logical`, and `physical`
+/// and `logical` themselves.
+fn thread_counts(logical: usize, physical: usize) -> Vec<usize> {
+    let mut counts: Vec<usize> = std::iter::successors(Some(1), |&n| Some(n * 2))
+        .take_while(|&n| n < logical)
+        .chain([physical.min(logical), logical])
+        .collect();
+    counts.sort_unstable();
+    counts.dedup();
+    counts
+}
+
+/// The mean and sample variance of `samples`.
+fn mean_and_variance(samples: &[f64]) -> (f64, f64) {
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    let variance = if samples.len() > 1 {
+        samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / (n - 1.0)
+    } else {
+        0.0
+    };
+    (mean, variance)
+}
+
+/// How one view rendered with one number of threads.
+struct Measurement {
+    view: &'static str,
+    threads: usize,
+    /// The mean and variance of the time taken, in seconds.
+    mean: f64,
+    variance: f64,
+    mpixels_per_second: f64,
+    /// How many times faster than with one thread.
+    speedup: f64,
+    /// The speedup per thread: 1 is perfect scaling.
+    efficiency: f64,
+}
+
+/// Render `view` at size `bounds` with `threads` threads, `runs` times, and
+/// return how long each run took, in seconds.
+fn time_view(view: &View, bounds: (usize, usize), threads: usize, runs: usize) -> Vec<f64> {
+    let center = Complex::new(view.center.0, view.center.1);
+    let (upper_left, lower_right) = viewport(center, view.width, bounds);
+    let rows_per_band = bounds.1 / threads + 1;
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            render_bands(
+                &mut pixels,
+                bounds,
+                upper_left,
+                lower_right,
+                rows_per_band,
+                &BandHooks::default(),
+                |band, band_bounds, band_upper_left, band_lower_right| {
+                    render(
+                        band,
+                        band_bounds,
+                        band_upper_left,
+                        band_lower_right,
+                        view.limit,
+                    )
+                },
+            );
+            start.elapsed().as_secs_f64()
+        })
+        .collect()
+}
+
+/// Entry point for `mandelbrot bench [--pixels WxH] [--runs N] [--json]`.
+pub fn bench_main(args: &[String]) {
+    let mut args = args.to_vec();
+    let bounds = take_option(&mut args, "--pixels")
+        .map(|s| parsed(parse_size(&s), "image dimensions"))
+        .unwrap_or((640, 480));
+    let runs = take_option(&mut args, "--runs")
+        .map(|s| parsed(usize::from_str(&s), "run count"))
+        .unwrap_or(3);
+    let json = take_flag(&mut args, "--json");
+
+    if args.len() != 2 || runs == 0 {
+        eprintln!(
+            "Usage: {} bench [--pixels WxH] [--runs N] [--json]",
+            args[0]
+        );
+        eprintln!("Example: {} bench --pixels 1080p --runs 5", args[0]);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let logical_cpus = num_cpus::get();
+    let physical_cpus = num_cpus::get_physical();
+    let counts = thread_counts(logical_cpus, physical_cpus);
+    let megapixels = (bounds.0 * bounds.1) as f64 / 1e6;
+
+    if !json {
+        println!(
+            "{}x{} pixels, {} runs each; {} logical CPUs, {} physical cores",
+            bounds.0, bounds.1, runs, logical_cpus, physical_cpus
+        );
+        println!(
+            "{:<16} {:>7} {:>10} {:>10} {:>9} {:>8} {:>10}",
+            "view", "threads", "mean (ms)", "± (ms)", "Mpixel/s", "speedup", "efficiency"
+        );
+    }
+    let mut measurements = Vec::new();
+    for view in &VIEWS {
+        let mut single = None;
+        for &threads in &counts {
+            let (mean, variance) = mean_and_variance(&time_view(view, bounds, threads, runs));
+            // The first count is always 1.
+            let single = *single.get_or_insert(mean);
+            let measurement = Measurement {
+                view: view.name,
+                threads,
+                mean,
+                variance,
+                mpixels_per_second: megapixels / mean,
+                speedup: single / mean,
+                efficiency: single / mean / threads as f64,
+            };
+            if !json {
+                let note = match (threads == physical_cpus, threads == logical_cpus) {
+                    (true, true) => "  physical = logical",
+                    (true, false) => "  physical",
+                    (false, true) => "  logical",
+                    (false, false) => "",
+                };
+                println!(
+                    "{:<16} {:>7} {:>10.1} {:>10.1} {:>9.2} {:>8.2} {:>10.2}{}",
+                    measurement.view,
+                    measurement.threads,
+                    1000.0 * measurement.mean,
+                    1000.0 * measurement.variance.sqrt(),
+                    measurement.mpixels_per_second,
+                    measurement.speedup,
+                    measurement.efficiency,
+                    note
+                );
+            }
+            measurements.push(measurement);
+        }
+    }
+
+    if json {
+        println!(
+            "{}",
+            to_json(bounds, runs, logical_cpus, physical_cpus, &measurements)
+        );
+    }
+}
+
+/// The results as a JSON object, for scripts to pick up.
+fn to_json(
+    bounds: (usize, usize),
+    runs: usize,
+    logical_cpus: usize,
+    physical_cpus: usize,
+    measurements: &[Measurement],
+) -> String {
+    let results: Vec<String> = measurements
+        .iter()
+        .map(|m| {
+            format!(
+                "    {{\"view\": \"{}\", \"threads\": {}, \"mean_seconds\": {:e}, \
+                 \"variance\": {:e}, \"mpixels_per_second\": {:.4}, \"speedup\": {:.4}, \
+                 \"efficiency\": {:.4}}}",
+                m.view,
+                m.threads,
+                m.mean,
+                m.variance,
+                m.mpixels_per_second,
+                m.speedup,
+                m.efficiency
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"width\": {},\n  \"height\": {},\n  \"runs\": {},\n  \
+         \"logical_cpus\": {},\n  \"physical_cpus\": {},\n  \"results\": [\n{}\n  ]\n}}",
+        bounds.0,
+        bounds.1,
+        runs,
+        logical_cpus,
+        physical_cpus,
+        results.join(",\n")
+    )
+}
+
+#[test]
+fn test_thread_counts() {
+    assert_eq!(thread_counts(1, 1), vec![1]);
+    assert_eq!(thread_counts(8, 4), vec![1, 2, 4, 8]);
+    assert_eq!(thread_counts(12, 6), vec![1, 2, 4, 6, 8, 12]);
+    // Some machines report more cores than the process may use.
+    assert_eq!(thread_counts(2, 4), vec![1, 2]);
+}
+
+#[test]
+fn test_mean_and_variance() {
+    assert_eq!(mean_and_variance(&[2.0]), (2.0, 0.0));
+    assert_eq!(mean_and_variance(&[1.0, 2.0, 3.0, 6.0]), (3.0, 14.0 / 3.0));
+
+    let json = to_json(
+        (4, 3),
+        2,
+        2,
+        1,
+        &[Measurement {
+            view: "whole set",
+            threads: 2,
+            mean: 0.5,
+            variance: 0.0,
+            mpixels_per_second: 0.000024,
+            speedup: 1.5,
+            efficiency: 0.75,
+        }],
+    );
+    assert!(json.contains("\"width\": 4"));
+    assert!(json.contains("\"view\": \"whole set\", \"threads\": 2, \"mean_seconds\": 5e-1"));
+    assert!(json.contains("\"efficiency\": 0.7500}"));
+}
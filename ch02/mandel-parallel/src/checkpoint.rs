@@ -0,0 +1,204 @@
+//! Saving finished bands to disk so an interrupted render can pick up where
+//! it left off.
+//!
+//! A checkpoint directory holds a `manifest` describing the render and one
+//! `band-NNNN` file per finished band. Every band file starts with a header
+//! line repeating the render parameters, the band's place in the image and a
+//! checksum of its pixels; a band is only reused if all of these match.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A directory of finished bands for one particular render.
+pub struct Checkpoint {
+    dir: PathBuf,
+    params: String,
+    rows_per_band: usize,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint directory `dir` for the render described by
+    /// `params`, a single line naming everything that affects its pixels:
+    /// size, corners, iteration limit and so on.
+    ///
+    /// When `resume` is set and the directory already holds a checkpoint of
+    /// the same render, its band layout is kept so its bands can be reused;
+    /// otherwise the render is laid out in bands of `rows_per_band` rows, and
+    /// any bands already in the directory are removed, so that a fresh
+    /// render never picks up stale ones.
+    pub fn open(
+        dir: &Path,
+        params: &str,
+        rows_per_band: usize,
+        resume: bool,
+    ) -> io::Result<Checkpoint> {
+        fs::create_dir_all(dir)?;
+
+        let params = params.to_string();
+        let manifest = dir.join("manifest");
+
+        if resume {
+            if let Ok(text) = fs::read_to_string(&manifest) {
+                if let Some(rows) = text
+                    .strip_prefix(&params)
+                    .and_then(|rest| rest.strip_prefix(" rows="))
+                    .and_then(|rows| rows.trim().parse().ok())
+                {
+                    return Ok(Checkpoint {
+                        dir: dir.to_path_buf(),
+                        params,
+                        rows_per_band: rows,
+                    });
+                }
+                eprintln!(
+                    "checkpoint in '{}' is for a different render; starting over",
+                    dir.display()
+                );
+            }
+        }
+
+        write_atomically(
+            &manifest,
+            format!("{} rows={}\n", params, rows_per_band).as_bytes(),
+        )?;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with("band-") {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(Checkpoint {
+            dir: dir.to_path_buf(),
+            params,
+            rows_per_band,
+        })
+    }
+
+    /// The number of rows in each band of this render.
+    pub fn rows_per_band(&self) -> usize {
+        self.rows_per_band
+    }
+
+    fn band_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("band-{:04}", index))
+    }
+
+    fn band_header(&self, index: usize, top: usize, pixels: &[u8]) -> String {
+        format!(
+            "{} band={} top={} len={} fnv={:016x}\n",
+            self.params,
+            index,
+            top,
+            pixels.len(),
+            fnv1a(pixels)
+        )
+    }
+
+    /// Return the saved pixels of band `index`, which starts at row `top`
+    /// and holds `len` pixels, if a valid copy is in the checkpoint.
+    pub fn load(&self, index: usize, top: usize, len: usize) -> Option<Vec<u8>> {
+        let data = fs::read(self.band_path(index)).ok()?;
+        let newline = data.iter().position(|&b| b == b'\n')?;
+        let (header, pixels) = (&data[..=newline], &data[newline + 1..]);
+
+        if pixels.len() != len || header != self.band_header(index, top, pixels).as_bytes() {
+            return None;
+        }
+        Some(pixels.to_vec())
+    }
+
+    /// Save the finished pixels of band `index`, which starts at row `top`.
+    pub fn save(&self, index: usize, top: usize, pixels: &[u8]) -> io::Result<()> {
+        let mut data = self.band_header(index, top, pixels).into_bytes();
+        data.extend_from_slice(pixels);
+        write_atomically(&self.band_path(index), &data)
+    }
+}
+
+/// Write `data` to `path` by way of a temporary file, so that an interrupted
+/// write never leaves a truncated file behind under the real name.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+/// The 64-bit FNV-1a hash of `data`.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mandel-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn test_checkpoint_round_trip() {
+    let dir = scratch_dir("checkpoint-round-trip");
+    let checkpoint = Checkpoint::open(&dir, "4x6 limit=255", 3, false).unwrap();
+
+    assert_eq!(checkpoint.load(1, 3, 12), None);
+    let band: Vec<u8> = (0..12).collect();
+    checkpoint.save(1, 3, &band).unwrap();
+    assert_eq!(checkpoint.load(1, 3, 12), Some(band.clone()));
+    assert_eq!(checkpoint.load(1, 3, 8), None);
+
+    // Damage one pixel: the checksum no longer matches.
+    let path = checkpoint.band_path(1);
+    let mut data = fs::read(&path).unwrap();
+    *data.last_mut().unwrap() ^= 1;
+    fs::write(&path, data).unwrap();
+    assert_eq!(checkpoint.load(1, 3, 12), None);
+
+    // A different render in the same directory ignores the old bands.
+    checkpoint.save(1, 3, &band).unwrap();
+    let other = Checkpoint::open(&dir, "4x6 limit=100", 2, true).unwrap();
+    assert_eq!(other.rows_per_band(), 2);
+    assert_eq!(other.load(1, 3, 12), None);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_checkpoint_resume_keeps_band_layout() {
+    let dir = scratch_dir("checkpoint-layout");
+    Checkpoint::open(&dir, "4x6 limit=255", 3, false).unwrap();
+
+    let resumed = Checkpoint::open(&dir, "4x6 limit=255", 5, true).unwrap();
+    assert_eq!(resumed.rows_per_band(), 3);
+    let fresh = Checkpoint::open(&dir, "4x6 limit=255", 5, false).unwrap();
+    assert_eq!(fresh.rows_per_band(), 5);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_checkpoint_without_resume_starts_over() {
+    let dir = scratch_dir("checkpoint-fresh");
+    let band: Vec<u8> = (0..12).collect();
+    let checkpoint = Checkpoint::open(&dir, "4x6 limit=255", 3, false).unwrap();
+    checkpoint.save(0, 0, &band).unwrap();
+    checkpoint.save(1, 3, &band).unwrap();
+
+    // The same render again, but not resumed: the saved bands are dropped.
+    let fresh = Checkpoint::open(&dir, "4x6 limit=255", 3, false).unwrap();
+    assert_eq!(fresh.load(0, 0, 12), None);
+    assert_eq!(fresh.load(1, 3, 12), None);
+    assert!(!fresh.band_path(0).exists() && !fresh.band_path(1).exists());
+    assert!(dir.join("manifest").exists());
+
+    // Bands saved since are kept for a resumed run.
+    fresh.save(1, 3, &band).unwrap();
+    let resumed = Checkpoint::open(&dir, "4x6 limit=255", 3, true).unwrap();
+    assert_eq!(resumed.load(1, 3, 12), Some(band));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
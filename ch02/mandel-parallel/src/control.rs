@@ -0,0 +1,125 @@
+//! Keeping an eye on a long render: counting the rows done, showing progress
+//! and an estimate of the time left, and stopping early on request.
+//!
+//! `render_bands` renders each band a slice of rows at a time when given a
+//! `Control`, counting the rows off as it goes and checking between slices
+//! whether to stop.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many rows a band renders between checks for cancellation.
+pub const SLICE_ROWS: usize = 16;
+
+/// The shared state of a render in progress.
+pub struct Control {
+    total_rows: usize,
+    rows_done: AtomicUsize,
+    cancelled: AtomicBool,
+    start: Instant,
+}
+
+impl Control {
+    /// Start watching a render of `total_rows` rows.
+    pub fn new(total_rows: usize) -> Control {
+        Control {
+            total_rows,
+            rows_done: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+            start: Instant::now(),
+        }
+    }
+
+    /// Ask the render to stop. Slices already being rendered are finished;
+    /// no more are started.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Count `rows` more rows as done.
+    pub fn add_rows(&self, rows: usize) {
+        self.rows_done.fetch_add(rows, Ordering::SeqCst);
+    }
+
+    pub fn rows_done(&self) -> usize {
+        self.rows_done.load(Ordering::SeqCst)
+    }
+
+    /// An estimate of the time left, from how fast rows have been finishing
+    /// so far, or `None` before any have.
+    pub fn remaining(&self) -> Option<Duration> {
+        let done = self.rows_done();
+        if done == 0 {
+            return None;
+        }
+        let left = self.total_rows.saturating_sub(done);
+        Some(self.start.elapsed().mul_f64(left as f64 / done as f64))
+    }
+
+    /// A one-line summary of the progress so far.
+    pub fn summary(&self) -> String {
+        let done = self.rows_done();
+        let percent = 100.0 * done as f64 / self.total_rows.max(1) as f64;
+        let eta = match self.remaining() {
+            Some(left) => format!("about {:.0?} left", left),
+            None => "estimating time left".to_string(),
+        };
+        format!(
+            "rows {}/{} ({:.0}%), {}",
+            done, self.total_rows, percent, eta
+        )
+    }
+
+    /// Print `summary` to stderr every so often until `finished` is set,
+    /// overwriting the line in place if stderr is a terminal.
+    pub fn show_progress(&self, finished: &AtomicBool) {
+        let terminal = io::stderr().is_terminal();
+        let interval = if terminal {
+            Duration::from_millis(200)
+        } else {
+            Duration::from_secs(5)
+        };
+        let mut last = Instant::now();
+        while !finished.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(50));
+            if last.elapsed() < interval {
+                continue;
+            }
+            last = Instant::now();
+            if terminal {
+                eprint!("\r{}\x1b[K", self.summary());
+                let _ = io::stderr().flush();
+            } else {
+                eprintln!("{}", self.summary());
+            }
+        }
+        if terminal {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+#[test]
+fn test_control() {
+    let control = Control::new(100);
+    assert_eq!(control.remaining(), None);
+    assert!(control.summary().starts_with("rows 0/100 (0%)"));
+
+    control.add_rows(25);
+    control.add_rows(25);
+    assert_eq!(control.rows_done(), 50);
+    // Half done, so about as long again to go.
+    let left = control.remaining().unwrap();
+    assert!(left <= control.start.elapsed() + Duration::from_millis(1));
+    assert!(control.summary().starts_with("rows 50/100 (50%)"));
+
+    assert!(!control.is_cancelled());
+    control.cancel();
+    assert!(control.is_cancelled());
+}
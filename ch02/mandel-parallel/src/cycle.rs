@@ -0,0 +1,189 @@
+//! Palette cycling: animating a rendered image by rotating its colors, the
+//! way demos of the 1980s did, without iterating a single point again.
+//!
+//! The gray levels from `render` are used as indices into a palette, and
+//! each frame shifts the palette along by an equal step, so the last frame
+//! leads back into the first; a palette whose ends meet, like the rainbow,
+//! cycles without a seam. Level 0, the set itself, stays black throughout. A
+//! GIF can give each frame its own palette, so every frame shares the same
+//! pixels; an APNG has one palette for the whole file, so its frames shift
+//! the pixels instead.
+
+use crate::palette::Palette;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The number of frames in a cycle, unless `--frames` says otherwise.
+pub const DEFAULT_FRAMES: usize = 32;
+
+/// How long each frame is shown, in hundredths of a second: the unit GIF
+/// counts in.
+const FRAME_DELAY: u16 = 5;
+
+/// The levels that cycle: all but 0.
+const CYCLING: usize = 255;
+
+/// Where level `level` is in the cycle on frame `frame` of `frames`, as a
+/// level that cycles itself. Level 0 stays put.
+fn shifted(level: u8, frame: usize, frames: usize) -> u8 {
+    if level == 0 {
+        return 0;
+    }
+    let shift = frame * CYCLING / frames;
+    (1 + (level as usize - 1 + shift) % CYCLING) as u8
+}
+
+/// Write an animation of `frames` frames cycling `palette` through the
+/// image `pixels`, whose dimensions are given by `bounds`, to `filename`, as
+/// a GIF or an APNG according to its extension.
+pub fn write_cycle(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (usize, usize),
+    frames: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let extension = Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let mut output = BufWriter::new(match extension.as_deref() {
+        Some("gif") | Some("png") | Some("apng") => File::create(filename)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' should end in .gif, .png or .apng", filename),
+            ))
+        }
+    });
+    match extension.as_deref() {
+        Some("gif") => write_gif(&mut output, pixels, bounds, frames, palette)?,
+        _ => write_apng(&mut output, pixels, bounds, frames, palette)?,
+    }
+    output.flush()
+}
+
+/// Write the animation as a looping GIF, with a palette for each frame.
+pub fn write_gif(
+    output: &mut impl Write,
+    pixels: &[u8],
+    bounds: (usize, usize),
+    frames: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    let (width, height) = match (u16::try_from(bounds.0), u16::try_from(bounds.1)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a GIF can be at most 65535 pixels across and down",
+            ))
+        }
+    };
+    let mut encoder = gif::Encoder::new(output, width, height, &[]).map_err(io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(io::Error::other)?;
+
+    for frame in 0..frames {
+        let frame_palette = (0..=255)
+            .flat_map(|level| palette.color(shifted(level, frame, frames)))
+            .collect();
+        let frame = gif::Frame {
+            delay: FRAME_DELAY,
+            width,
+            height,
+            palette: Some(frame_palette),
+            buffer: Cow::Borrowed(pixels),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+/// Write the animation as a looping APNG, with the pixels shifted through
+/// one palette for each frame.
+pub fn write_apng(
+    output: &mut impl Write,
+    pixels: &[u8],
+    bounds: (usize, usize),
+    frames: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    let mut encoder = png::Encoder::new(output, bounds.0 as u32, bounds.1 as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.plte());
+    encoder.set_animated(frames as u32, 0)?;
+    encoder.set_frame_delay(FRAME_DELAY, 100)?;
+    let mut writer = encoder.write_header()?;
+
+    let mut data = vec![0; pixels.len()];
+    for frame in 0..frames {
+        for (shifted_level, &level) in data.iter_mut().zip(pixels) {
+            *shifted_level = shifted(level, frame, frames);
+        }
+        writer.write_image_data(&data)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[test]
+fn test_shifted() {
+    assert_eq!(shifted(0, 5, 8), 0);
+    assert_eq!(shifted(1, 0, 8), 1);
+    assert_eq!(shifted(255, 0, 8), 255);
+    // Levels wrap around from 255 to 1, skipping the set's.
+    assert_eq!(shifted(255, 1, 255), 1);
+    assert_eq!(shifted(10, 4, 8), 10 + 127);
+}
+
+#[test]
+fn test_gif_and_apng_agree() {
+    let bounds = (4, 3);
+    let pixels = [0, 1, 2, 50, 100, 150, 200, 250, 255, 17, 0, 128];
+    let frames = 3;
+
+    let mut gif_data = Vec::new();
+    write_gif(&mut gif_data, &pixels, bounds, frames, &Palette::rainbow()).unwrap();
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut gif_decoder = options.read_info(gif_data.as_slice()).unwrap();
+
+    let mut apng_data = Vec::new();
+    write_apng(&mut apng_data, &pixels, bounds, frames, &Palette::rainbow()).unwrap();
+    let mut apng_decoder = png::Decoder::new(apng_data.as_slice());
+    apng_decoder.set_transformations(png::Transformations::EXPAND);
+    let mut apng_reader = apng_decoder.read_info().unwrap();
+    assert_eq!(
+        apng_reader.info().animation_control().unwrap().num_frames,
+        frames as u32
+    );
+
+    let mut previous = None;
+    for _ in 0..frames {
+        let gif_frame = gif_decoder.read_next_frame().unwrap().unwrap();
+        let gif_rgb: Vec<u8> = gif_frame
+            .buffer
+            .chunks(4)
+            .flat_map(|rgba| rgba[..3].to_vec())
+            .collect();
+
+        let mut apng_rgb = vec![0; apng_reader.output_buffer_size()];
+        apng_reader.next_frame(&mut apng_rgb).unwrap();
+        assert_eq!(gif_rgb, apng_rgb);
+
+        // The set stays black while everything else changes color.
+        assert_eq!(&apng_rgb[..3], &[0, 0, 0]);
+        if let Some(previous) = previous.replace(apng_rgb.clone()) {
+            assert_ne!(previous[3..], apng_rgb[3..]);
+        }
+    }
+    assert!(gif_decoder.read_next_frame().unwrap().is_none());
+}
@@ -0,0 +1,165 @@
+//! Coloring by where an escaping orbit ends up, rather than only how soon:
+//! the argument of `z` once it has passed the escape radius.
+//!
+//! Binary decomposition splits each band of equal escape time in two by the
+//! sign of that `z`'s imaginary part. The cells it makes are bounded by
+//! external rays at angles `k / 2^n` and by equipotentials, so they show how
+//! the set's bulbs and filaments are connected. Field lines draw the rays
+//! themselves, as thin lines wherever the escaped `z` lies along the
+//! positive real axis.
+
+use crate::{escape, gray_level, pixel_to_point};
+use num::{Complex, Float};
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+
+/// Orbits are followed out to this radius. The larger it is, the more
+/// closely the edges of the cells follow the true external rays, and the
+/// more nearly the field lines run on smoothly from one band to the next.
+const RADIUS: f64 = 1e3;
+
+/// How many field lines are drawn for each turn of the escaped `z` about
+/// the origin, and how wide they are, as a fraction of the space between
+/// them. A band `n` iterations out is crossed by `2^n` turns, so the lines
+/// double from each band to the next, as the external rays do.
+const LINES: f64 = 1.0;
+const LINE_WIDTH: f64 = 0.1;
+
+/// The gray level of the cells where `z` escapes below the real axis.
+const LOWER_CELL: u8 = 96;
+
+/// The colorings selectable with `--coloring`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coloring {
+    /// White above the real axis, gray below it.
+    Binary,
+    /// The usual gray levels, with dark lines along external rays.
+    FieldLines,
+}
+
+impl FromStr for Coloring {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Coloring, String> {
+        match s {
+            "binary" => Ok(Coloring::Binary),
+            "field-lines" => Ok(Coloring::FieldLines),
+            _ => Err(format!("unknown coloring '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Coloring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Coloring::Binary => "binary",
+            Coloring::FieldLines => "field-lines",
+        })
+    }
+}
+
+impl Coloring {
+    /// The gray level for a point that escapes after `count` of `limit`
+    /// iterations as `z`, or that never escapes if `escaped` is `None`.
+    fn level<T: Float>(self, escaped: Option<(usize, Complex<T>)>, limit: usize) -> u8 {
+        let (count, z) = match escaped {
+            None => return 0,
+            Some(escaped) => escaped,
+        };
+        match self {
+            Coloring::Binary => {
+                if z.im >= T::zero() {
+                    255
+                } else {
+                    LOWER_CELL
+                }
+            }
+            Coloring::FieldLines => {
+                let level = gray_level(Some(count), limit);
+                let turns = z.arg().to_f64().unwrap() / TAU;
+                let offset = (turns * LINES).rem_euclid(1.0);
+                if offset.min(1.0 - offset) < LINE_WIDTH / 2.0 {
+                    level / 4
+                } else {
+                    level
+                }
+            }
+        }
+    }
+}
+
+/// Render a rectangle of the Mandelbrot set into a buffer of pixels, colored
+/// by `coloring`. Points in the set are black.
+///
+/// The other arguments are the same as for `render`.
+pub fn render_decomposed<T: Float>(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<T>,
+    lower_right: Complex<T>,
+    limit: usize,
+    coloring: Coloring,
+) {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    let radius = T::from(RADIUS).unwrap();
+
+    for row in 0..bounds.1 {
+        for col in 0..bounds.0 {
+            let point = pixel_to_point(bounds, (col, row), upper_left, lower_right);
+            pixels[row * bounds.0 + col] = coloring.level(escape(point, limit, radius), limit);
+        }
+    }
+}
+
+#[test]
+fn test_parse_coloring() {
+    assert_eq!("binary".parse(), Ok(Coloring::Binary));
+    assert_eq!("field-lines".parse(), Ok(Coloring::FieldLines));
+    assert!("rainbow".parse::<Coloring>().is_err());
+    assert_eq!(Coloring::FieldLines.to_string(), "field-lines");
+}
+
+#[test]
+fn test_decomposition_is_mirrored() {
+    // The set is symmetric about the real axis, and so are the orbits, so
+    // reflecting a point swaps the half its cell is in.
+    let (upper, lower) = (Complex::new(-0.8, 0.3), Complex::new(-0.8, -0.3));
+    let (above, below) = (escape(upper, 255, RADIUS), escape(lower, 255, RADIUS));
+    assert_eq!(above.unwrap().0, below.unwrap().0);
+    assert_ne!(
+        Coloring::Binary.level(above, 255),
+        Coloring::Binary.level(below, 255)
+    );
+
+    // Points in the set are black however they're colored.
+    for coloring in [Coloring::Binary, Coloring::FieldLines] {
+        assert_eq!(
+            coloring.level(escape(Complex::new(-1.0, 0.0), 255, RADIUS), 255),
+            0
+        );
+    }
+}
+
+#[test]
+fn test_field_lines() {
+    // Along the positive real axis the escaped `z` has argument zero, which is
+    // on a line; across each band the argument turns through a full circle,
+    // so some points are on lines and most are not.
+    let on_axis = escape(Complex::new(0.5, 0.0), 255, RADIUS);
+    assert_eq!(
+        Coloring::FieldLines.level(on_axis, 255),
+        gray_level(Some(on_axis.unwrap().0), 255) / 4
+    );
+
+    let mut on_line = 0;
+    let steps = 200;
+    for i in 0..steps {
+        let c = Complex::new(0.5, 0.5 * i as f64 / steps as f64);
+        let escaped = escape(c, 255, RADIUS);
+        if Coloring::FieldLines.level(escaped, 255) != gray_level(Some(escaped.unwrap().0), 255) {
+            on_line += 1;
+        }
+    }
+    assert!(on_line > 0 && on_line < steps / 2, "{}", on_line);
+}
@@ -0,0 +1,580 @@
+//! Spreading a render across several processes over TCP.
+//!
+//! A worker listens on a socket and renders whatever bands it is sent. The
+//! coordinator splits the image into horizontal bands just as the threaded
+//! renderer does, hands them out to its workers and copies each reply into
+//! place. If a worker goes away, or takes longer than the reply timeout to
+//! answer, the band it was rendering goes back on the queue for the others
+//! to pick up.
+//!
+//! The protocol is deliberately simple. A job is one line of text:
+//!
+//! ```text
+//! BAND index width height ul.re ul.im lr.re lr.im limit
+//! ```
+//!
+//! and the reply is a line `PIXELS index length` followed by `length` bytes
+//! of grayscale pixels, exactly as `render` produced them.
+
+use crate::parse::{parse_size, MAX_PIXELS};
+use crate::{
+    invalid, io_or_exit, parsed, parsed_view, pixel_to_point, render, take_option, write_image,
+    DEFAULT_LIMIT, EXIT_USAGE,
+};
+use num::Complex;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How many bands the coordinator cuts the image into per worker
+/// connection, so that a fast worker isn't left idle behind a slow one.
+const BANDS_PER_WORKER: usize = 4;
+
+/// How long to wait for a worker to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for a worker to answer a job, unless `--timeout` says
+/// otherwise. This should be well over the time a band takes to render: a
+/// worker that takes longer is given up on as hung.
+const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A horizontal band of the image, described in terms a worker can render
+/// without knowing anything about the rest of the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BandJob {
+    pub index: usize,
+    pub bounds: (usize, usize),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub limit: usize,
+}
+
+/// What a coordinator thread reports back about the worker it is driving.
+enum Reply {
+    Band(usize, Vec<u8>),
+    /// The worker at this address failed, with the band it had taken, if
+    /// it had got as far as taking one.
+    Lost(String, io::Error, Option<usize>),
+}
+
+/// Split an image of size `bounds` covering `upper_left` to `lower_right`
+/// into bands of `rows_per_band` rows, the last band taking what is left.
+pub fn band_jobs(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    limit: usize,
+    rows_per_band: usize,
+) -> Vec<BandJob> {
+    (0..bounds.1)
+        .step_by(rows_per_band)
+        .enumerate()
+        .map(|(index, top)| {
+            let height = rows_per_band.min(bounds.1 - top);
+            BandJob {
+                index,
+                bounds: (bounds.0, height),
+                upper_left: pixel_to_point(bounds, (0, top), upper_left, lower_right),
+                lower_right: pixel_to_point(
+                    bounds,
+                    (bounds.0, top + height),
+                    upper_left,
+                    lower_right,
+                ),
+                limit,
+            }
+        })
+        .collect()
+}
+
+/// Write `job` to a worker as a single line.
+fn send_job(writer: &mut impl Write, job: &BandJob) -> io::Result<()> {
+    writeln!(
+        writer,
+        "BAND {} {} {} {} {} {} {} {}",
+        job.index,
+        job.bounds.0,
+        job.bounds.1,
+        job.upper_left.re,
+        job.upper_left.im,
+        job.lower_right.re,
+        job.lower_right.im,
+        job.limit
+    )?;
+    writer.flush()
+}
+
+/// Parse a job line as written by `send_job`. A band bigger than any image
+/// the command line accepts is refused rather than allocated.
+fn parse_job(line: &str) -> Option<BandJob> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 9 || fields[0] != "BAND" {
+        return None;
+    }
+    let number = |i: usize| fields[i].parse::<f64>().ok();
+    let count = |i: usize| fields[i].parse::<usize>().ok();
+    let bounds = (count(2)?, count(3)?);
+    if bounds.0.checked_mul(bounds.1)? > MAX_PIXELS {
+        return None;
+    }
+    Some(BandJob {
+        index: count(1)?,
+        bounds,
+        upper_left: Complex {
+            re: number(4)?,
+            im: number(5)?,
+        },
+        lower_right: Complex {
+            re: number(6)?,
+            im: number(7)?,
+        },
+        limit: count(8).filter(|&limit| limit > 0)?,
+    })
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Serve band jobs on every connection accepted by `listener`, one thread
+/// per connection. Runs until the listener fails.
+pub fn serve(listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            if let Err(e) = serve_connection(stream) {
+                eprintln!("worker: connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Render jobs read from `stream` until the coordinator hangs up.
+fn serve_connection(stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let job = parse_job(&line)
+            .ok_or_else(|| invalid_data(format!("malformed job {:?}", line.trim_end())))?;
+
+        let mut pixels = vec![0; job.bounds.0 * job.bounds.1];
+        render(
+            &mut pixels,
+            job.bounds,
+            job.upper_left,
+            job.lower_right,
+            job.limit,
+        );
+
+        writeln!(writer, "PIXELS {} {}", job.index, pixels.len())?;
+        writer.write_all(&pixels)?;
+        writer.flush()?;
+    }
+}
+
+/// Send `job` down a worker connection and wait for its pixels.
+fn exchange(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    job: &BandJob,
+) -> io::Result<Vec<u8>> {
+    send_job(writer, job)?;
+
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "worker closed the connection",
+        ));
+    }
+    let expected = job.bounds.0 * job.bounds.1;
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    if fields != ["PIXELS", &job.index.to_string(), &expected.to_string()] {
+        return Err(invalid_data(format!(
+            "unexpected reply {:?}",
+            header.trim_end()
+        )));
+    }
+
+    let mut pixels = vec![0; expected];
+    reader.read_exact(&mut pixels)?;
+    Ok(pixels)
+}
+
+/// Connect to the worker at `address`, trying each address it resolves to
+/// for up to `CONNECT_TIMEOUT`.
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for socket_address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' resolves to no address", address),
+        )
+    }))
+}
+
+/// Feed jobs from `queue` to the worker at `address` until every band is
+/// done, giving up on the worker if it doesn't answer a job within
+/// `reply_timeout`. A band that fails is put back on the queue before
+/// returning the error along with its index, so that another worker can
+/// render it.
+fn drive_worker(
+    address: &str,
+    jobs: &[BandJob],
+    queue: &Mutex<VecDeque<usize>>,
+    remaining: &AtomicUsize,
+    replies: &mpsc::Sender<Reply>,
+    reply_timeout: Duration,
+) -> Result<(), (io::Error, Option<usize>)> {
+    let connected = connect(address).and_then(|stream| {
+        stream.set_read_timeout(Some(reply_timeout))?;
+        stream.set_write_timeout(Some(reply_timeout))?;
+        Ok((BufReader::new(stream.try_clone()?), BufWriter::new(stream)))
+    });
+    let (mut reader, mut writer) = connected.map_err(|e| (e, None))?;
+
+    loop {
+        let next = queue.lock().unwrap().pop_front();
+        let index = match next {
+            Some(index) => index,
+            None if remaining.load(Ordering::SeqCst) == 0 => return Ok(()),
+            None => {
+                // Another worker may yet drop out and give its band back.
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        };
+
+        match exchange(&mut reader, &mut writer, &jobs[index]) {
+            Ok(pixels) => {
+                remaining.fetch_sub(1, Ordering::SeqCst);
+                let _ = replies.send(Reply::Band(index, pixels));
+            }
+            Err(e) => {
+                queue.lock().unwrap().push_back(index);
+                // Timeouts show up as `WouldBlock` on some platforms.
+                let e = match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no reply within {:?}", reply_timeout),
+                    ),
+                    _ => e,
+                };
+                return Err((e, Some(index)));
+            }
+        }
+    }
+}
+
+/// Render `jobs` on the workers listening at `workers`, copying each band
+/// into its place in `pixels`. The bands must tile `pixels` in order.
+///
+/// Bands whose worker disconnects, or doesn't answer within
+/// `reply_timeout`, are reassigned to the remaining workers; this only fails
+/// if every worker has been lost.
+pub fn render_distributed(
+    pixels: &mut [u8],
+    jobs: &[BandJob],
+    workers: &[String],
+    reply_timeout: Duration,
+) -> io::Result<()> {
+    let mut offsets = Vec::with_capacity(jobs.len());
+    let mut offset = 0;
+    for job in jobs {
+        offsets.push(offset);
+        offset += job.bounds.0 * job.bounds.1;
+    }
+    assert!(offset == pixels.len());
+
+    let queue = Mutex::new((0..jobs.len()).collect::<VecDeque<_>>());
+    let remaining = AtomicUsize::new(jobs.len());
+    let (sender, receiver) = mpsc::channel();
+
+    crossbeam::scope(|spawner| {
+        for address in workers {
+            let sender = sender.clone();
+            let (queue, remaining) = (&queue, &remaining);
+            spawner.spawn(move |_| {
+                if let Err((e, band)) =
+                    drive_worker(address, jobs, queue, remaining, &sender, reply_timeout)
+                {
+                    let _ = sender.send(Reply::Lost(address.clone(), e, band));
+                }
+            });
+        }
+        drop(sender);
+
+        for reply in receiver {
+            match reply {
+                Reply::Band(index, band) => {
+                    pixels[offsets[index]..offsets[index] + band.len()].copy_from_slice(&band);
+                }
+                Reply::Lost(address, e, Some(index)) => {
+                    eprintln!("lost worker {}: {}; reassigning band {}", address, e, index);
+                }
+                Reply::Lost(address, e, None) => eprintln!("lost worker {}: {}", address, e),
+            }
+        }
+    })
+    .unwrap();
+
+    match remaining.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        n => Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            format!("all workers were lost with {} bands unrendered", n),
+        )),
+    }
+}
+
+/// Entry point for `mandelbrot worker ADDRESS`.
+pub fn worker_main(args: &[String]) {
+    if args.len() != 3 {
+        eprintln!("Usage: {} worker ADDRESS", args[0]);
+        eprintln!("Example: {} worker 0.0.0.0:7878", args[0]);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let listener = io_or_exit(TcpListener::bind(&args[2]), "binding worker address");
+    println!("worker listening on {}", listener.local_addr().unwrap());
+    io_or_exit(serve(listener), "accepting connections");
+}
+
+/// Entry point for `mandelbrot coordinator [--iterations N] [--timeout SECS]
+/// FILE PIXELS UPPERLEFT LOWERRIGHT WORKER...`.
+///
+/// Naming the same worker more than once opens several connections to it,
+/// which lets a many-core worker render several bands at a time.
+pub fn coordinator_main(args: &[String]) {
+    let mut args = args.to_vec();
+    let limit = take_option(&mut args, "--iterations")
+        .map(|s| parsed(usize::from_str(&s), "iteration limit"))
+        .unwrap_or(DEFAULT_LIMIT);
+    let reply_timeout = take_option(&mut args, "--timeout")
+        .map(|s| Duration::from_secs(parsed(u64::from_str(&s), "timeout")))
+        .unwrap_or(DEFAULT_REPLY_TIMEOUT);
+    if args.len() < 7 {
+        eprintln!(
+            "Usage: {} coordinator [--iterations N] [--timeout SECS] \
+             FILE PIXELS UPPERLEFT LOWERRIGHT WORKER...",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} coordinator mandel.png 4000x3000 -1.20,0.35 -1,0.20 \
+             host1:7878 host2:7878",
+            args[0]
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if limit == 0 {
+        invalid("--iterations must be at least 1");
+    }
+    if reply_timeout.is_zero() {
+        invalid("--timeout must be at least 1");
+    }
+
+    let bounds = parsed(parse_size(&args[3]), "image dimensions");
+    let (upper_left, lower_right) = parsed_view(&[&args[4], &args[5]], bounds);
+    let workers = &args[6..];
+
+    let rows_per_band = bounds.1 / (workers.len() * BANDS_PER_WORKER) + 1;
+    let jobs = band_jobs(bounds, upper_left, lower_right, limit, rows_per_band);
+    println!(
+        "sending {} bands of up to {} rows to {} workers",
+        jobs.len(),
+        rows_per_band,
+        workers.len()
+    );
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    io_or_exit(
+        render_distributed(&mut pixels, &jobs, workers, reply_timeout),
+        "rendering on workers",
+    );
+
+    io_or_exit(
+        write_image(&args[2], &pixels, bounds, None),
+        "writing PNG file",
+    );
+}
+
+#[cfg(test)]
+fn local_workers(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            thread::spawn(move || serve(listener));
+            address
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn render_locally(jobs: &[BandJob]) -> Vec<u8> {
+    let mut pixels = Vec::new();
+    for job in jobs {
+        let mut band = vec![0; job.bounds.0 * job.bounds.1];
+        render(
+            &mut band,
+            job.bounds,
+            job.upper_left,
+            job.lower_right,
+            job.limit,
+        );
+        pixels.extend(band);
+    }
+    pixels
+}
+
+#[test]
+fn test_parse_job_round_trip() {
+    let job = BandJob {
+        index: 3,
+        bounds: (100, 7),
+        upper_left: Complex { re: -1.2, im: 0.35 },
+        lower_right: Complex {
+            re: -1.0,
+            im: 0.1 + 0.2,
+        },
+        limit: 255,
+    };
+    let mut line = Vec::new();
+    send_job(&mut line, &job).unwrap();
+    assert_eq!(parse_job(std::str::from_utf8(&line).unwrap()), Some(job));
+    assert_eq!(parse_job("BAND 1 2 3"), None);
+    assert_eq!(parse_job("BAND 0 10 10 0,0 1 1 0 255"), None);
+    assert_eq!(parse_job("BAND 0 100000 100000 0 0 1 1 255"), None);
+    assert_eq!(parse_job("BAND 0 18446744073709551615 2 0 0 1 1 255"), None);
+}
+
+#[test]
+fn test_band_jobs_cover_image() {
+    let jobs = band_jobs(
+        (10, 25),
+        Complex { re: -1.0, im: 1.0 },
+        Complex { re: 1.0, im: -1.0 },
+        255,
+        7,
+    );
+    let heights: Vec<usize> = jobs.iter().map(|job| job.bounds.1).collect();
+    assert_eq!(heights, vec![7, 7, 7, 4]);
+    assert_eq!(jobs[3].lower_right, Complex { re: 1.0, im: -1.0 });
+}
+
+#[test]
+fn test_distributed_matches_local_render() {
+    let bounds = (120, 90);
+    // Not the default iteration limit, which the workers must be told of.
+    let jobs = band_jobs(
+        bounds,
+        Complex {
+            re: -1.20,
+            im: 0.35,
+        },
+        Complex { re: -1.0, im: 0.20 },
+        1000,
+        10,
+    );
+    let workers = local_workers(3);
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    render_distributed(&mut pixels, &jobs, &workers, DEFAULT_REPLY_TIMEOUT).unwrap();
+    assert_eq!(pixels, render_locally(&jobs));
+}
+
+#[test]
+fn test_distributed_reassigns_lost_bands() {
+    // A worker that takes one job and hangs up without answering.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let flaky = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+    });
+
+    let bounds = (64, 48);
+    let jobs = band_jobs(
+        bounds,
+        Complex { re: -2.0, im: 1.0 },
+        Complex { re: 1.0, im: -1.0 },
+        DEFAULT_LIMIT,
+        5,
+    );
+    let mut workers = local_workers(1);
+    workers.insert(0, flaky);
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    render_distributed(&mut pixels, &jobs, &workers, DEFAULT_REPLY_TIMEOUT).unwrap();
+    assert_eq!(pixels, render_locally(&jobs));
+}
+
+#[test]
+fn test_distributed_fails_without_workers() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    drop(listener);
+
+    let jobs = band_jobs(
+        (8, 8),
+        Complex { re: -2.0, im: 1.0 },
+        Complex { re: 1.0, im: -1.0 },
+        DEFAULT_LIMIT,
+        4,
+    );
+    let mut pixels = vec![0; 64];
+    assert!(render_distributed(&mut pixels, &jobs, &[address], DEFAULT_REPLY_TIMEOUT).is_err());
+}
+
+#[test]
+fn test_distributed_gives_up_on_hung_workers() {
+    // A worker that takes one job and never answers, but stays connected.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let hung = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        thread::sleep(Duration::from_secs(60));
+    });
+
+    let bounds = (64, 48);
+    let jobs = band_jobs(
+        bounds,
+        Complex { re: -2.0, im: 1.0 },
+        Complex { re: 1.0, im: -1.0 },
+        DEFAULT_LIMIT,
+        5,
+    );
+    let mut workers = local_workers(1);
+    workers.insert(0, hung);
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    render_distributed(&mut pixels, &jobs, &workers, Duration::from_millis(200)).unwrap();
+    assert_eq!(pixels, render_locally(&jobs));
+}
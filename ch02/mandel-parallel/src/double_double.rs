@@ -0,0 +1,528 @@
+//! Double-double arithmetic: a number carried as the unevaluated sum of two
+//! `f64`s, giving about 106 bits of mantissa, twice what `f64` has.
+//!
+//! Addition, subtraction, multiplication, division and square roots are
+//! carried out at full double-double precision, using the error-free
+//! transformations from Dekker and Knuth. That is all the renderer needs.
+//! The transcendental functions `Float` also asks for are computed in `f64`
+//! on the leading part only.
+
+use num::traits::{Num, NumCast, One, ToPrimitive, Zero};
+use num::Float;
+use std::cmp::Ordering;
+use std::fmt;
+use std::num::FpCategory;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::str::FromStr;
+
+/// A double-double number, `hi + lo` with `|lo|` at most half an ulp of `hi`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+/// The sum `a + b` as a rounded value and its exact rounding error.
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+/// Like `two_sum`, but only correct when `|a| >= |b|`.
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+/// The product `a * b` as a rounded value and its exact rounding error.
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    (p, a.mul_add(b, -p))
+}
+
+impl DoubleDouble {
+    /// The double-double equal to `hi`.
+    pub fn from_f64(hi: f64) -> DoubleDouble {
+        DoubleDouble { hi, lo: 0.0 }
+    }
+
+    fn new(hi: f64, lo: f64) -> DoubleDouble {
+        let (hi, lo) = quick_two_sum(hi, lo);
+        DoubleDouble { hi, lo }
+    }
+
+    /// Apply `f` to the leading part, for functions we don't carry at full
+    /// precision.
+    fn map_hi(self, f: impl FnOnce(f64) -> f64) -> DoubleDouble {
+        DoubleDouble::from_f64(f(self.hi))
+    }
+
+    fn mul_f64(self, b: f64) -> DoubleDouble {
+        let (p, e) = two_prod(self.hi, b);
+        DoubleDouble::new(p, e + self.lo * b)
+    }
+}
+
+impl From<f64> for DoubleDouble {
+    fn from(hi: f64) -> DoubleDouble {
+        DoubleDouble::from_f64(hi)
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn add(self, other: DoubleDouble) -> DoubleDouble {
+        let (s, e) = two_sum(self.hi, other.hi);
+        let (t, f) = two_sum(self.lo, other.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        DoubleDouble::new(s, e + f)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn sub(self, other: DoubleDouble) -> DoubleDouble {
+        self + -other
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn mul(self, other: DoubleDouble) -> DoubleDouble {
+        let (p, e) = two_prod(self.hi, other.hi);
+        DoubleDouble::new(p, e + (self.hi * other.lo + self.lo * other.hi))
+    }
+}
+
+impl Div for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn div(self, other: DoubleDouble) -> DoubleDouble {
+        // Long division, one f64's worth of quotient at a time.
+        let q1 = self.hi / other.hi;
+        let r = self - other.mul_f64(q1);
+        let q2 = r.hi / other.hi;
+        let r = r - other.mul_f64(q2);
+        let q3 = r.hi / other.hi;
+        DoubleDouble::new(q1, q2) + DoubleDouble::from_f64(q3)
+    }
+}
+
+impl Rem for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn rem(self, other: DoubleDouble) -> DoubleDouble {
+        self - (self / other).trunc() * other
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn neg(self) -> DoubleDouble {
+        DoubleDouble {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+
+impl Zero for DoubleDouble {
+    fn zero() -> DoubleDouble {
+        DoubleDouble::from_f64(0.0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.hi == 0.0
+    }
+}
+
+impl One for DoubleDouble {
+    fn one() -> DoubleDouble {
+        DoubleDouble::from_f64(1.0)
+    }
+}
+
+impl Num for DoubleDouble {
+    type FromStrRadixErr = ParseDoubleDoubleError;
+
+    fn from_str_radix(s: &str, radix: u32) -> Result<DoubleDouble, ParseDoubleDoubleError> {
+        if radix != 10 {
+            return Err(ParseDoubleDoubleError);
+        }
+        s.parse()
+    }
+}
+
+impl ToPrimitive for DoubleDouble {
+    fn to_i64(&self) -> Option<i64> {
+        let whole = self.trunc();
+        Some(whole.hi.to_i64()? + whole.lo.to_i64()?)
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        self.to_i64().and_then(|n| n.to_u64())
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(self.hi + self.lo)
+    }
+}
+
+impl NumCast for DoubleDouble {
+    fn from<N: ToPrimitive>(n: N) -> Option<DoubleDouble> {
+        // Go by way of i64 where we can, since that is exact.
+        if let Some(i) = n.to_i64() {
+            let hi = i as f64;
+            return Some(DoubleDouble::new(hi, (i - hi as i64) as f64));
+        }
+        n.to_f64().map(DoubleDouble::from_f64)
+    }
+}
+
+/// The error returned when a string isn't a decimal number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParseDoubleDoubleError;
+
+impl fmt::Display for ParseDoubleDoubleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid double-double literal")
+    }
+}
+
+impl FromStr for DoubleDouble {
+    type Err = ParseDoubleDoubleError;
+
+    /// Parse a decimal number such as `-1.25`, `.5` or `3e-20`, keeping all
+    /// the digits a double-double can hold rather than rounding to `f64`.
+    fn from_str(s: &str) -> Result<DoubleDouble, ParseDoubleDoubleError> {
+        let s = s.trim();
+        let (negative, s) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (mantissa, exponent) = match s.find(['e', 'E']) {
+            Some(index) => (
+                &s[..index],
+                s[index + 1..]
+                    .parse::<i32>()
+                    .map_err(|_| ParseDoubleDoubleError)?,
+            ),
+            None => (s, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(ParseDoubleDoubleError);
+        }
+
+        let ten = DoubleDouble::from_f64(10.0);
+        let mut value = DoubleDouble::zero();
+        for c in whole.chars().chain(fraction.chars()) {
+            let digit = c.to_digit(10).ok_or(ParseDoubleDoubleError)?;
+            value = value * ten + DoubleDouble::from_f64(digit as f64);
+        }
+        let scale = exponent - fraction.len() as i32;
+        value = if scale < 0 {
+            value / ten.powi(-scale)
+        } else {
+            value * ten.powi(scale)
+        };
+
+        Ok(if negative { -value } else { value })
+    }
+}
+
+impl fmt::Display for DoubleDouble {
+    /// Print all the significant digits, in scientific notation.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.hi.is_finite() || self.hi == 0.0 {
+            return write!(f, "{}", self.hi);
+        }
+
+        let ten = DoubleDouble::from_f64(10.0);
+        let mut exponent = self.hi.abs().log10().floor() as i32;
+        let mut r = self.abs() / ten.powi(exponent);
+        if r.hi >= 10.0 {
+            r = r / ten;
+            exponent += 1;
+        } else if r.hi < 1.0 {
+            r = r * ten;
+            exponent -= 1;
+        }
+
+        let mut digits = String::new();
+        for _ in 0..32 {
+            let digit = r.hi.floor().clamp(0.0, 9.0);
+            digits.push(char::from(b'0' + digit as u8));
+            r = (r - DoubleDouble::from_f64(digit)) * ten;
+        }
+        let digits = digits.trim_end_matches('0');
+        let (first, rest) = digits.split_at(1);
+
+        if self.hi < 0.0 {
+            f.write_str("-")?;
+        }
+        match rest {
+            "" => write!(f, "{}e{}", first, exponent),
+            _ => write!(f, "{}.{}e{}", first, rest, exponent),
+        }
+    }
+}
+
+impl Float for DoubleDouble {
+    fn nan() -> DoubleDouble {
+        DoubleDouble::from_f64(f64::NAN)
+    }
+    fn infinity() -> DoubleDouble {
+        DoubleDouble::from_f64(f64::INFINITY)
+    }
+    fn neg_infinity() -> DoubleDouble {
+        DoubleDouble::from_f64(f64::NEG_INFINITY)
+    }
+    fn neg_zero() -> DoubleDouble {
+        DoubleDouble::from_f64(-0.0)
+    }
+    fn min_value() -> DoubleDouble {
+        DoubleDouble::from_f64(f64::MIN)
+    }
+    fn min_positive_value() -> DoubleDouble {
+        DoubleDouble::from_f64(f64::MIN_POSITIVE)
+    }
+    fn max_value() -> DoubleDouble {
+        DoubleDouble::from_f64(f64::MAX)
+    }
+    fn epsilon() -> DoubleDouble {
+        DoubleDouble::from_f64(f64::EPSILON * f64::EPSILON)
+    }
+
+    fn is_nan(self) -> bool {
+        self.hi.is_nan()
+    }
+    fn is_infinite(self) -> bool {
+        self.hi.is_infinite()
+    }
+    fn is_finite(self) -> bool {
+        self.hi.is_finite()
+    }
+    fn is_normal(self) -> bool {
+        self.hi.is_normal()
+    }
+    fn classify(self) -> FpCategory {
+        self.hi.classify()
+    }
+    fn is_sign_positive(self) -> bool {
+        self.hi.is_sign_positive()
+    }
+    fn is_sign_negative(self) -> bool {
+        self.hi.is_sign_negative()
+    }
+
+    fn floor(self) -> DoubleDouble {
+        let hi = self.hi.floor();
+        if hi == self.hi {
+            DoubleDouble::new(hi, self.lo.floor())
+        } else {
+            DoubleDouble::from_f64(hi)
+        }
+    }
+    fn ceil(self) -> DoubleDouble {
+        -(-self).floor()
+    }
+    fn round(self) -> DoubleDouble {
+        let floor = self.floor();
+        match (self - floor).partial_cmp(&DoubleDouble::from_f64(0.5)) {
+            Some(Ordering::Less) => floor,
+            _ => floor + DoubleDouble::one(),
+        }
+    }
+    fn trunc(self) -> DoubleDouble {
+        if self.hi < 0.0 {
+            self.ceil()
+        } else {
+            self.floor()
+        }
+    }
+    fn fract(self) -> DoubleDouble {
+        self - self.trunc()
+    }
+    fn abs(self) -> DoubleDouble {
+        if self.hi < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
+    fn signum(self) -> DoubleDouble {
+        DoubleDouble::from_f64(self.hi.signum())
+    }
+    fn mul_add(self, a: DoubleDouble, b: DoubleDouble) -> DoubleDouble {
+        self * a + b
+    }
+    fn recip(self) -> DoubleDouble {
+        DoubleDouble::one() / self
+    }
+    fn powi(self, n: i32) -> DoubleDouble {
+        let mut result = DoubleDouble::one();
+        let mut base = self;
+        let mut n_abs = n.unsigned_abs();
+        while n_abs > 0 {
+            if n_abs & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n_abs >>= 1;
+        }
+        if n < 0 {
+            result.recip()
+        } else {
+            result
+        }
+    }
+    fn powf(self, n: DoubleDouble) -> DoubleDouble {
+        self.map_hi(|x| x.powf(n.hi))
+    }
+    fn sqrt(self) -> DoubleDouble {
+        if self.hi <= 0.0 {
+            return DoubleDouble::from_f64(self.hi.sqrt());
+        }
+        // One Newton step from the f64 estimate doubles the precision.
+        let x = 1.0 / self.hi.sqrt();
+        let ax = DoubleDouble::from_f64(self.hi * x);
+        ax + DoubleDouble::from_f64((self - ax * ax).hi * x * 0.5)
+    }
+    fn exp(self) -> DoubleDouble {
+        self.map_hi(f64::exp)
+    }
+    fn exp2(self) -> DoubleDouble {
+        self.map_hi(f64::exp2)
+    }
+    fn ln(self) -> DoubleDouble {
+        self.map_hi(f64::ln)
+    }
+    fn log(self, base: DoubleDouble) -> DoubleDouble {
+        self.map_hi(|x| x.log(base.hi))
+    }
+    fn log2(self) -> DoubleDouble {
+        self.map_hi(f64::log2)
+    }
+    fn log10(self) -> DoubleDouble {
+        self.map_hi(f64::log10)
+    }
+    fn max(self, other: DoubleDouble) -> DoubleDouble {
+        if self >= other || other.is_nan() {
+            self
+        } else {
+            other
+        }
+    }
+    fn min(self, other: DoubleDouble) -> DoubleDouble {
+        if self <= other || other.is_nan() {
+            self
+        } else {
+            other
+        }
+    }
+    fn abs_sub(self, other: DoubleDouble) -> DoubleDouble {
+        (self - other).max(DoubleDouble::zero())
+    }
+    fn cbrt(self) -> DoubleDouble {
+        self.map_hi(f64::cbrt)
+    }
+    fn hypot(self, other: DoubleDouble) -> DoubleDouble {
+        (self * self + other * other).sqrt()
+    }
+    fn sin(self) -> DoubleDouble {
+        self.map_hi(f64::sin)
+    }
+    fn cos(self) -> DoubleDouble {
+        self.map_hi(f64::cos)
+    }
+    fn tan(self) -> DoubleDouble {
+        self.map_hi(f64::tan)
+    }
+    fn asin(self) -> DoubleDouble {
+        self.map_hi(f64::asin)
+    }
+    fn acos(self) -> DoubleDouble {
+        self.map_hi(f64::acos)
+    }
+    fn atan(self) -> DoubleDouble {
+        self.map_hi(f64::atan)
+    }
+    fn atan2(self, other: DoubleDouble) -> DoubleDouble {
+        self.map_hi(|y| y.atan2(other.hi))
+    }
+    fn sin_cos(self) -> (DoubleDouble, DoubleDouble) {
+        (self.sin(), self.cos())
+    }
+    fn exp_m1(self) -> DoubleDouble {
+        self.map_hi(f64::exp_m1)
+    }
+    fn ln_1p(self) -> DoubleDouble {
+        self.map_hi(f64::ln_1p)
+    }
+    fn sinh(self) -> DoubleDouble {
+        self.map_hi(f64::sinh)
+    }
+    fn cosh(self) -> DoubleDouble {
+        self.map_hi(f64::cosh)
+    }
+    fn tanh(self) -> DoubleDouble {
+        self.map_hi(f64::tanh)
+    }
+    fn asinh(self) -> DoubleDouble {
+        self.map_hi(f64::asinh)
+    }
+    fn acosh(self) -> DoubleDouble {
+        self.map_hi(f64::acosh)
+    }
+    fn atanh(self) -> DoubleDouble {
+        self.map_hi(f64::atanh)
+    }
+    fn integer_decode(self) -> (u64, i16, i8) {
+        self.hi.integer_decode()
+    }
+}
+
+#[test]
+fn test_double_double_arithmetic() {
+    let third = DoubleDouble::one() / DoubleDouble::from_f64(3.0);
+    let one = third * DoubleDouble::from_f64(3.0);
+    assert!((one - DoubleDouble::one()).abs().hi < 1e-31);
+
+    // 1 + 2^-80 is lost in f64 but not here.
+    let tiny = DoubleDouble::from_f64(2.0).powi(-80);
+    let sum = DoubleDouble::one() + tiny;
+    assert_eq!(sum - DoubleDouble::one(), tiny);
+
+    let two = DoubleDouble::from_f64(2.0);
+    let root = two.sqrt();
+    assert!((root * root - two).abs().hi < 1e-30);
+    assert_eq!(
+        DoubleDouble::from_f64(7.0) % DoubleDouble::from_f64(4.0),
+        3.0.into()
+    );
+}
+
+#[test]
+fn test_double_double_parse_and_print() {
+    let x: DoubleDouble = "0.1".parse().unwrap();
+    // Ten tenths make one to well beyond f64 precision.
+    let ten_tenths = x * DoubleDouble::from_f64(10.0);
+    assert!((ten_tenths - DoubleDouble::one()).abs().hi < 1e-31);
+
+    assert_eq!("-1.25".parse(), Ok(DoubleDouble::from_f64(-1.25)));
+    assert_eq!("3e2".parse(), Ok(DoubleDouble::from_f64(300.0)));
+    assert_eq!(".5".parse(), Ok(DoubleDouble::from_f64(0.5)));
+    assert!("1.2.3".parse::<DoubleDouble>().is_err());
+    assert!("".parse::<DoubleDouble>().is_err());
+
+    assert_eq!(DoubleDouble::from_f64(-1.25).to_string(), "-1.25e0");
+    let precise: DoubleDouble = "0.1234567890123456789012345".parse().unwrap();
+    assert!(precise.to_string().starts_with("1.23456789012345678901234"));
+}
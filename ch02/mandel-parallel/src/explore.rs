@@ -0,0 +1,335 @@
+//! Unattended exploration: start from the whole set and keep zooming into
+//! whichever part of the current image looks most interesting, saving every
+//! step on the way down.
+//!
+//! A part of the image is interesting if its gray levels vary a lot or if
+//! it has much of the set's boundary in it. The choice among the best parts
+//! is random, so different seeds wander to different places, but the same
+//! seed always takes the same path.
+
+use crate::parse::parse_size;
+use crate::parse::viewport;
+use crate::{
+    io_or_exit, parsed, render, render_bands, take_option, write_image, BandHooks, DEFAULT_LIMIT,
+    EXIT_USAGE,
+};
+use num::Complex;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Each step divides the view into this many cells across and down, and
+/// zooms in on one of them.
+const GRID: usize = 4;
+
+/// How many of the highest-scoring cells the next view is chosen among.
+const CANDIDATES: usize = 3;
+
+/// Stop once a pixel is narrower than this many units in the last place of
+/// the view's coordinates, where `f64` can no longer tell pixels apart.
+const PRECISION_LIMIT: f64 = 64.0 * f64::EPSILON;
+
+/// The whole set, as the first view.
+const START_CENTER: Complex<f64> = Complex { re: -0.75, im: 0.0 };
+const START_WIDTH: f64 = 3.5;
+
+/// The SplitMix64 generator: tiny, fast, and plenty random for choosing
+/// where to look next.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number uniformly distributed in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// How interesting the pixels in the rectangle from (`left`, `top`) to
+/// (`right`, `bottom`), exclusive, of an image with dimensions `bounds` are.
+///
+/// The score adds the variance of the gray levels, scaled to lie between 0
+/// and 1, to the fraction of pixels next to a pixel on the other side of
+/// the set's boundary.
+fn score(pixels: &[u8], bounds: (usize, usize), cell: (usize, usize, usize, usize)) -> f64 {
+    let (left, top, right, bottom) = cell;
+    let count = ((right - left) * (bottom - top)) as f64;
+    if count == 0.0 {
+        return 0.0;
+    }
+    let at = |col: usize, row: usize| pixels[row * bounds.0 + col];
+
+    let (mut sum, mut sum_sq, mut boundary) = (0.0, 0.0, 0.0);
+    for row in top..bottom {
+        for col in left..right {
+            let value = at(col, row) as f64;
+            sum += value;
+            sum_sq += value * value;
+
+            let inside = at(col, row) == 0;
+            let neighbours = [
+                (col + 1 < bounds.0).then(|| at(col + 1, row)),
+                (row + 1 < bounds.1).then(|| at(col, row + 1)),
+            ];
+            if neighbours.iter().flatten().any(|&n| (n == 0) != inside) {
+                boundary += 1.0;
+            }
+        }
+    }
+    let mean = sum / count;
+    let variance = sum_sq / count - mean * mean;
+    variance / (255.0 * 255.0) + boundary / count
+}
+
+/// Choose which of the `GRID` by `GRID` cells of the image to zoom in on,
+/// returning its column and row in the grid, or `None` if nothing in the
+/// image is worth a closer look.
+pub fn choose_cell(pixels: &[u8], bounds: (usize, usize), rng: &mut Rng) -> Option<(usize, usize)> {
+    let mut scored = Vec::new();
+    for y in 0..GRID {
+        for x in 0..GRID {
+            let cell = (
+                x * bounds.0 / GRID,
+                y * bounds.1 / GRID,
+                (x + 1) * bounds.0 / GRID,
+                (y + 1) * bounds.1 / GRID,
+            );
+            scored.push(((x, y), score(pixels, bounds, cell)));
+        }
+    }
+    // Sort stably, best first, so ties always come out the same way.
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(CANDIDATES);
+    scored.retain(|&(_, score)| score > 0.0);
+
+    // Pick a candidate with probability in proportion to its score.
+    let total: f64 = scored.iter().map(|&(_, score)| score).sum();
+    let mut target = rng.next_f64() * total;
+    for &(cell, score) in &scored {
+        if target < score {
+            return Some(cell);
+        }
+        target -= score;
+    }
+    scored.last().map(|&(cell, _)| cell)
+}
+
+/// One step of an exploration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub center: Complex<f64>,
+    pub width: f64,
+    pub limit: usize,
+}
+
+/// Why an exploration ended.
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    /// It reached the requested depth.
+    Depth,
+    /// Pixels became too small for `f64` to tell apart.
+    Precision,
+    /// The last image had nothing in it worth zooming in on.
+    Featureless,
+}
+
+/// Explore from the whole set for `depth` steps or until there is nowhere
+/// further to go, rendering each step at size `bounds` and passing it to
+/// `visit`.
+pub fn explore<F>(bounds: (usize, usize), depth: usize, seed: u64, mut visit: F) -> io::Result<Stop>
+where
+    F: FnMut(usize, &Step, &[u8]) -> io::Result<()>,
+{
+    let mut rng = Rng::new(seed);
+    let mut step = Step {
+        center: START_CENTER,
+        width: START_WIDTH,
+        limit: DEFAULT_LIMIT,
+    };
+    let rows_per_band = bounds.1 / num_cpus::get() + 1;
+
+    for n in 0..depth {
+        let pixel_width = step.width / bounds.0 as f64;
+        if pixel_width < PRECISION_LIMIT * step.center.norm().max(1.0) {
+            return Ok(Stop::Precision);
+        }
+
+        let (upper_left, lower_right) = viewport(step.center, step.width, bounds);
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        render_bands(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            rows_per_band,
+            &BandHooks::default(),
+            |band, band_bounds, band_upper_left, band_lower_right| {
+                render(
+                    band,
+                    band_bounds,
+                    band_upper_left,
+                    band_lower_right,
+                    step.limit,
+                )
+            },
+        );
+        visit(n, &step, &pixels)?;
+
+        let (x, y) = match choose_cell(&pixels, bounds, &mut rng) {
+            Some(cell) => cell,
+            None => return Ok(Stop::Featureless),
+        };
+        let height = step.width * bounds.1 as f64 / bounds.0 as f64;
+        let cell_center = Complex::new(
+            upper_left.re + (x as f64 + 0.5) * step.width / GRID as f64,
+            upper_left.im - (y as f64 + 0.5) * height / GRID as f64,
+        );
+        step = Step {
+            center: cell_center,
+            width: step.width / GRID as f64,
+            // Deeper views need more iterations to show their detail.
+            limit: step.limit + DEFAULT_LIMIT,
+        };
+    }
+    Ok(Stop::Depth)
+}
+
+/// Entry point for `mandelbrot explore [--seed N] [--depth N] [--pixels WxH] DIR`.
+///
+/// Writes each step's image to DIR as `step-NNN.png`, and its viewport to
+/// `DIR/viewports.txt` as the renderer's arguments for redrawing it:
+/// `--iterations N FILE PIXELS UPPERLEFT LOWERRIGHT`.
+pub fn explore_main(args: &[String]) {
+    let mut args = args.to_vec();
+    let seed = take_option(&mut args, "--seed")
+        .map(|s| parsed(u64::from_str(&s), "seed"))
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+    let depth = take_option(&mut args, "--depth")
+        .map(|s| parsed(usize::from_str(&s), "depth"))
+        .unwrap_or(10);
+    let bounds = take_option(&mut args, "--pixels")
+        .map(|s| parsed(parse_size(&s), "image dimensions"))
+        .unwrap_or((1000, 750));
+
+    if args.len() != 3 || bounds.0 < GRID || bounds.1 < GRID {
+        eprintln!(
+            "Usage: {} explore [--seed N] [--depth N] [--pixels WxH] DIR",
+            args[0]
+        );
+        eprintln!("Example: {} explore --seed 7 --depth 12 walls", args[0]);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let dir = Path::new(&args[2]);
+    io_or_exit(fs::create_dir_all(dir), "creating output directory");
+    let mut log = BufWriter::new(io_or_exit(
+        File::create(dir.join("viewports.txt")),
+        "creating viewport list",
+    ));
+    io_or_exit(writeln!(log, "# seed {}", seed), "writing viewport list");
+    println!("exploring with seed {}", seed);
+
+    let stop = explore(bounds, depth, seed, |n, step, pixels| {
+        let filename = dir.join(format!("step-{:03}.png", n));
+        write_image(&filename.to_string_lossy(), pixels, bounds, None)?;
+        let (upper_left, lower_right) = viewport(step.center, step.width, bounds);
+        let line = format!(
+            "--iterations {} {} {}x{} {},{} {},{}",
+            step.limit,
+            filename.display(),
+            bounds.0,
+            bounds.1,
+            upper_left.re,
+            upper_left.im,
+            lower_right.re,
+            lower_right.im
+        );
+        println!("{}", line);
+        writeln!(log, "{}", line)?;
+        log.flush()
+    });
+    let stop = io_or_exit(stop, "writing exploration step");
+
+    match stop {
+        Stop::Depth => println!("reached depth {}", depth),
+        Stop::Precision => println!("stopped at the limit of f64 precision"),
+        Stop::Featureless => println!("stopped: nothing left to zoom in on"),
+    }
+}
+
+#[test]
+fn test_rng_is_reproducible() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    let mut c = Rng::new(43);
+    let first: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+    assert_eq!(first, (0..5).map(|_| b.next_u64()).collect::<Vec<_>>());
+    assert_ne!(first, (0..5).map(|_| c.next_u64()).collect::<Vec<_>>());
+    // The first output of SplitMix64 seeded with zero.
+    assert_eq!(Rng::new(0).next_u64(), 0xe220_a839_7b1d_cdaf);
+    for _ in 0..1000 {
+        let x = a.next_f64();
+        assert!((0.0..1.0).contains(&x));
+    }
+}
+
+#[test]
+fn test_choose_cell() {
+    let mut rng = Rng::new(1);
+    assert_eq!(choose_cell(&[255; 64], (8, 8), &mut rng), None);
+
+    // All the detail is in the bottom right corner, though the boundary
+    // pixels next to it spill into the two cells beside it.
+    let mut pixels = vec![255; 64];
+    pixels[7 * 8 + 6] = 0;
+    pixels[6 * 8 + 7] = 0;
+    let mut counts = std::collections::HashMap::new();
+    for _ in 0..100 {
+        let cell = choose_cell(&pixels, (8, 8), &mut rng).unwrap();
+        *counts.entry(cell).or_insert(0) += 1;
+    }
+    assert!(counts
+        .keys()
+        .all(|cell| [(3, 3), (2, 3), (3, 2)].contains(cell)));
+    assert!(counts[&(3, 3)] > 50);
+}
+
+#[test]
+fn test_explore_is_reproducible() {
+    let run = |seed| {
+        let mut steps = Vec::new();
+        let stop = explore((48, 36), 4, seed, |_, step, pixels| {
+            assert_eq!(pixels.len(), 48 * 36);
+            steps.push(step.clone());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(stop, Stop::Depth);
+        steps
+    };
+    let steps = run(5);
+    assert_eq!(steps.len(), 4);
+    assert_eq!(steps, run(5));
+    for pair in steps.windows(2) {
+        assert_eq!(pair[1].width, pair[0].width / GRID as f64);
+        assert!((pair[1].center - pair[0].center).norm() < pair[0].width);
+    }
+}
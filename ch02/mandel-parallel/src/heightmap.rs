@@ -0,0 +1,405 @@
+//! Turning a rendered image into terrain: 16-bit displacement maps and
+//! triangulated meshes for rendering or 3D printing.
+//!
+//! The height of each point is its smoothed escape time, the same
+//! continuous field `--light` shades, so the set itself is the lowest ground
+//! and the land rises smoothly away from it rather than in 256 terraces, as
+//! the gray levels from `render` would. The field is computed in a pass of
+//! its own after the image is rendered, so asking for terrain about doubles
+//! the time a render takes.
+//!
+//! Meshes are closed solids, with walls down to a flat base, so that slicers
+//! for 3D printers accept them as they are.
+
+use crate::lighting::smooth_escape;
+use crate::pixel_to_point;
+use image::png::PNGEncoder;
+use image::ColorType;
+use num::{Complex, Float};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+
+/// How far below the lowest point of the terrain a mesh's base lies, in the
+/// same units as its grid spacing.
+const BASE_THICKNESS: f64 = 1.0;
+
+/// A grid of heights between 0 and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightField {
+    bounds: (usize, usize),
+    heights: Vec<f64>,
+}
+
+impl HeightField {
+    /// The height field of the view with corners `upper_left` and
+    /// `lower_right` at size `bounds`: points escaping at once stand at 1,
+    /// and those still bounded after `limit` iterations at 0. The rows are
+    /// divided among `threads` threads.
+    pub fn from_escape<T: Float + Send + Sync>(
+        bounds: (usize, usize),
+        upper_left: Complex<T>,
+        lower_right: Complex<T>,
+        limit: usize,
+        threads: usize,
+    ) -> HeightField {
+        let mut heights = vec![0.0; bounds.0 * bounds.1];
+        let rows_per_band = bounds.1 / threads + 1;
+        thread::scope(|scope| {
+            for (i, band) in heights.chunks_mut(rows_per_band * bounds.0).enumerate() {
+                scope.spawn(move || {
+                    for (j, height) in band.iter_mut().enumerate() {
+                        let pixel = (j % bounds.0, i * rows_per_band + j / bounds.0);
+                        let point = pixel_to_point(bounds, pixel, upper_left, lower_right);
+                        *height = match smooth_escape(point, limit) {
+                            Some(time) => (1.0 - time / limit as f64).clamp(0.0, 1.0),
+                            None => 0.0,
+                        };
+                    }
+                });
+            }
+        });
+        HeightField { bounds, heights }
+    }
+
+    fn height(&self, col: usize, row: usize) -> f64 {
+        self.heights[row * self.bounds.0 + col]
+    }
+
+    /// Soften the terrain with `passes` rounds of a 3x3 box blur, for
+    /// gentler slopes where the escape time changes quickly. Edge pixels
+    /// average over the neighbours they have.
+    pub fn smooth(&mut self, passes: usize) {
+        let (width, height) = self.bounds;
+        for _ in 0..passes {
+            let mut smoothed = Vec::with_capacity(self.heights.len());
+            for row in 0..height {
+                for col in 0..width {
+                    let (mut sum, mut count) = (0.0, 0.0);
+                    for r in row.saturating_sub(1)..(row + 2).min(height) {
+                        for c in col.saturating_sub(1)..(col + 2).min(width) {
+                            sum += self.height(c, r);
+                            count += 1.0;
+                        }
+                    }
+                    smoothed.push(sum / count);
+                }
+            }
+            self.heights = smoothed;
+        }
+    }
+
+    /// Write the field to `filename` as a 16-bit grayscale PNG.
+    pub fn write_png16(&self, filename: &str) -> io::Result<()> {
+        // PNG stores 16-bit samples most significant byte first.
+        let data: Vec<u8> = self
+            .heights
+            .iter()
+            .flat_map(|&h| ((h * 65535.0).round() as u16).to_be_bytes())
+            .collect();
+        let output = File::create(filename)?;
+        let encoder = PNGEncoder::new(output);
+        encoder.encode(
+            &data,
+            self.bounds.0 as u32,
+            self.bounds.1 as u32,
+            ColorType::Gray(16),
+        )
+    }
+
+    /// Build a closed mesh of the terrain, taking every `step`th sample in
+    /// each direction. The grid spacing is one unit per sample taken and the
+    /// highest possible point stands `scale` units above the lowest. Fails
+    /// if that leaves fewer than 2x2 samples, as `check_mesh` explains.
+    pub fn to_mesh(&self, scale: f64, step: usize) -> io::Result<Mesh> {
+        check_samples(self.bounds, step)?;
+        let cols: Vec<usize> = (0..self.bounds.0).step_by(step).collect();
+        let rows: Vec<usize> = (0..self.bounds.1).step_by(step).collect();
+        let (width, height) = (cols.len(), rows.len());
+
+        let mut mesh = Mesh::default();
+
+        // The top surface, with image rows running down the y axis.
+        for (y, &row) in rows.iter().enumerate() {
+            for (x, &col) in cols.iter().enumerate() {
+                mesh.vertices.push([
+                    x as f64,
+                    (height - 1 - y) as f64,
+                    self.height(col, row) * scale,
+                ]);
+            }
+        }
+        let top = |x: usize, y: usize| y * width + x;
+        for y in 0..height - 1 {
+            for x in 0..width - 1 {
+                let (a, b) = (top(x, y), top(x + 1, y));
+                let (c, d) = (top(x, y + 1), top(x + 1, y + 1));
+                mesh.triangles.push([a, c, b]);
+                mesh.triangles.push([b, c, d]);
+            }
+        }
+
+        // Walk the edge of the grid counterclockwise as seen from above,
+        // dropping a wall from each edge segment to the base.
+        let mut edge = Vec::new();
+        edge.extend((0..width - 1).map(|x| (x, height - 1)));
+        edge.extend((1..height).rev().map(|y| (width - 1, y)));
+        edge.extend((1..width).rev().map(|x| (x, 0)));
+        edge.extend((0..height - 1).map(|y| (0, y)));
+
+        let base = -BASE_THICKNESS;
+        let first_bottom = mesh.vertices.len();
+        for &(x, y) in &edge {
+            let [vx, vy, _] = mesh.vertices[top(x, y)];
+            mesh.vertices.push([vx, vy, base]);
+        }
+        let center = mesh.vertices.len();
+        mesh.vertices
+            .push([(width - 1) as f64 / 2.0, (height - 1) as f64 / 2.0, base]);
+
+        for i in 0..edge.len() {
+            let j = (i + 1) % edge.len();
+            let (top_i, top_j) = (top(edge[i].0, edge[i].1), top(edge[j].0, edge[j].1));
+            let (bottom_i, bottom_j) = (first_bottom + i, first_bottom + j);
+            mesh.triangles.push([bottom_i, bottom_j, top_j]);
+            mesh.triangles.push([bottom_i, top_j, top_i]);
+            mesh.triangles.push([center, bottom_j, bottom_i]);
+        }
+
+        Ok(mesh)
+    }
+}
+
+/// Check that a mesh of an image of size `bounds`, taking every `step`th
+/// sample, can be written to `filename`: that the name ends in `.obj` or
+/// `.stl`, and that there are at least two samples each way to make the
+/// triangles of. This lets a bad choice be reported before rendering rather
+/// than after.
+pub fn check_mesh(filename: &str, bounds: (usize, usize), step: usize) -> io::Result<()> {
+    mesh_format(filename)?;
+    check_samples(bounds, step)
+}
+
+fn check_samples(bounds: (usize, usize), step: usize) -> io::Result<()> {
+    // Taking every `step`th of `n` pixels leaves (n - 1) / step + 1 samples.
+    if step == 0 || bounds.0 <= step || bounds.1 <= step {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "a mesh needs at least 2x2 samples, and a {}x{} image at a step of {} has fewer",
+                bounds.0, bounds.1, step
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The mesh format `filename` calls for by its extension, in lowercase.
+fn mesh_format(filename: &str) -> io::Result<String> {
+    match Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+    {
+        Some(extension) if extension == "obj" || extension == "stl" => Ok(extension),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' should end in .obj or .stl", filename),
+        )),
+    }
+}
+
+/// A triangle mesh. Triangles list their vertices counterclockwise as seen
+/// from outside.
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<[f64; 3]>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    /// Write the mesh to `filename`, as Wavefront OBJ or binary STL
+    /// according to its extension.
+    pub fn write(&self, filename: &str) -> io::Result<()> {
+        let format = mesh_format(filename)?;
+        let mut output = BufWriter::new(File::create(filename)?);
+        match format.as_str() {
+            "obj" => self.write_obj(&mut output)?,
+            _ => self.write_stl(&mut output)?,
+        }
+        output.flush()
+    }
+
+    /// Write the mesh as Wavefront OBJ text.
+    pub fn write_obj(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "# Mandelbrot terrain")?;
+        for [x, y, z] in &self.vertices {
+            writeln!(output, "v {} {} {:.6}", x, y, z)?;
+        }
+        for [a, b, c] in &self.triangles {
+            // OBJ counts vertices from one.
+            writeln!(output, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Write the mesh as binary STL.
+    pub fn write_stl(&self, output: &mut impl Write) -> io::Result<()> {
+        let mut header = [0u8; 80];
+        header[..19].copy_from_slice(b"Mandelbrot terrain ");
+        output.write_all(&header)?;
+        output.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|i| self.vertices[i]);
+            let normal = normalize(cross(sub(b, a), sub(c, a)));
+            for v in [normal, a, b, c] {
+                for coordinate in v {
+                    output.write_all(&(coordinate as f32).to_le_bytes())?;
+                }
+            }
+            output.write_all(&[0, 0])?;
+        }
+        Ok(())
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length == 0.0 {
+        return v;
+    }
+    v.map(|c| c / length)
+}
+
+/// A field with the heights of the gray levels `levels`, for testing.
+#[cfg(test)]
+fn from_levels(levels: &[u8], bounds: (usize, usize)) -> HeightField {
+    assert!(levels.len() == bounds.0 * bounds.1);
+    HeightField {
+        bounds,
+        heights: levels.iter().map(|&p| p as f64 / 255.0).collect(),
+    }
+}
+
+#[test]
+fn test_from_escape() {
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    let bounds = (60, 40);
+    let field = HeightField::from_escape(bounds, upper_left, lower_right, 255, 3);
+    assert_eq!(
+        field,
+        HeightField::from_escape(bounds, upper_left, lower_right, 255, 1)
+    );
+    // The origin is in the set; the far corner escapes almost at once.
+    assert_eq!(field.height(40, 20), 0.0);
+    assert!(field.height(0, 0) > 0.95);
+
+    // More levels than the 256 gray ones, even in this small image.
+    let mut levels: Vec<u16> = field
+        .heights
+        .iter()
+        .map(|&h| (h * 65535.0).round() as u16)
+        .collect();
+    levels.sort_unstable();
+    levels.dedup();
+    assert!(levels.len() > 256, "{} levels", levels.len());
+}
+
+#[test]
+fn test_smooth() {
+    let mut flat = from_levels(&[100; 12], (4, 3));
+    let before = flat.clone();
+    flat.smooth(2);
+    for (a, b) in flat.heights.iter().zip(&before.heights) {
+        assert!((a - b).abs() < 1e-12);
+    }
+
+    let mut spike = from_levels(&[0, 0, 0, 0, 255, 0, 0, 0, 0], (3, 3));
+    spike.smooth(1);
+    assert!((spike.height(1, 1) - 1.0 / 9.0).abs() < 1e-12);
+    assert!((spike.height(0, 0) - 1.0 / 4.0).abs() < 1e-12);
+}
+
+#[test]
+fn test_mesh_is_closed() {
+    let field = from_levels(&[0, 64, 128, 255, 32, 16, 8, 4, 2, 1, 0, 9], (4, 3));
+    let mesh = field.to_mesh(10.0, 1).unwrap();
+
+    // 12 top vertices, 10 around the base and one in its middle; two
+    // triangles per grid cell and three per edge segment.
+    assert_eq!(mesh.vertices.len(), 12 + 10 + 1);
+    assert_eq!(mesh.triangles.len(), 2 * 6 + 3 * 10);
+    assert_eq!(mesh.vertices[3], [3.0, 2.0, 10.0]);
+
+    // In a closed, consistently wound mesh every edge is used exactly once
+    // in each direction.
+    let mut edges = std::collections::HashMap::new();
+    for &[a, b, c] in &mesh.triangles {
+        for edge in [(a, b), (b, c), (c, a)] {
+            *edges.entry(edge).or_insert(0) += 1;
+        }
+    }
+    for (&(a, b), &count) in &edges {
+        assert_eq!(count, 1);
+        assert_eq!(edges.get(&(b, a)), Some(&1));
+    }
+
+    // Every top face points up.
+    for &[a, b, c] in &mesh.triangles[..12] {
+        let [a, b, c] = [a, b, c].map(|i| mesh.vertices[i]);
+        assert!(cross(sub(b, a), sub(c, a))[2] > 0.0);
+    }
+}
+
+#[test]
+fn test_mesh_formats() {
+    let field = from_levels(&[0; 25], (5, 5));
+    let mesh = field.to_mesh(1.0, 2).unwrap();
+    assert_eq!(mesh.vertices.len(), 9 + 8 + 1);
+
+    let mut stl = Vec::new();
+    mesh.write_stl(&mut stl).unwrap();
+    assert_eq!(stl.len(), 84 + 50 * mesh.triangles.len());
+
+    let mut obj = Vec::new();
+    mesh.write_obj(&mut obj).unwrap();
+    let obj = String::from_utf8(obj).unwrap();
+    assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 18);
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("f ")).count(),
+        mesh.triangles.len()
+    );
+    assert!(obj.contains("\nf 1 4 2\n"));
+}
+
+#[test]
+fn test_mesh_checks() {
+    assert!(check_mesh("terrain.obj", (10, 10), 9).is_ok());
+    assert!(check_mesh("terrain.STL", (10, 10), 1).is_ok());
+    assert!(check_mesh("terrain.ply", (10, 10), 1).is_err());
+    assert!(check_mesh("terrain", (10, 10), 1).is_err());
+    // Too few samples to make a single grid cell of.
+    assert!(check_mesh("terrain.stl", (10, 10), 10).is_err());
+    assert!(check_mesh("terrain.stl", (10, 10), 20).is_err());
+    assert!(check_mesh("terrain.stl", (1, 10), 1).is_err());
+
+    let field = from_levels(&[0; 10], (1, 10));
+    assert!(field.to_mesh(1.0, 1).is_err());
+    let field = from_levels(&[0; 100], (10, 10));
+    assert!(field.to_mesh(1.0, 20).is_err());
+}
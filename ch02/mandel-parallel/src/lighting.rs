@@ -0,0 +1,231 @@
+//! Slope shading: treating the smoothed escape time as the height of a
+//! surface and lighting it, for the embossed look common in Mandelbrot art.
+//!
+//! The surface normal at each pixel comes from the difference between the
+//! heights of its neighbours, so a band needs a one-pixel halo of heights
+//! around it. The escape field is defined everywhere, so the halo is just
+//! computed along with the band, and the seams between bands come out the
+//! same as anywhere else.
+
+use crate::escape;
+use num::{Complex, Float};
+use std::str::FromStr;
+
+/// Escaping orbits are followed out to this radius before smoothing. The
+/// larger it is, the more nearly the smoothed field's slope is continuous
+/// where the escape count steps up, and the less the steps show.
+const SMOOTH_RADIUS: f64 = 1e5;
+
+/// How steeply the surface tilts, as the ratio of horizontal to vertical in
+/// its normals. The tilt is the same everywhere; only its direction varies.
+const RELIEF: f64 = 1.0;
+
+/// The weights of the parts of the Blinn-Phong lighting model, and the
+/// shininess of the highlights.
+const AMBIENT: f64 = 0.15;
+const DIFFUSE: f64 = 0.7;
+const SPECULAR: f64 = 0.3;
+const SHININESS: i32 = 20;
+
+/// Where the light comes from: `azimuth` degrees counterclockwise from the
+/// right of the image, `elevation` degrees above its plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub azimuth: f64,
+    pub elevation: f64,
+}
+
+impl FromStr for Light {
+    type Err = String;
+
+    /// Parse `AZIMUTH,ELEVATION`, in degrees.
+    fn from_str(s: &str) -> Result<Light, String> {
+        match crate::parse_pair(s, ',') {
+            Some((azimuth, elevation)) if (0.0..=90.0).contains(&elevation) => {
+                Ok(Light { azimuth, elevation })
+            }
+            Some(_) => Err(format!(
+                "light elevation in '{}' must be 0 to 90 degrees",
+                s
+            )),
+            None => Err(format!("expected AZIMUTH,ELEVATION, not '{}'", s)),
+        }
+    }
+}
+
+impl Light {
+    /// The unit vector pointing towards the light.
+    fn direction(&self) -> [f64; 3] {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        [
+            elevation.cos() * azimuth.cos(),
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+        ]
+    }
+}
+
+/// The escape time of `c` smoothed into a continuous value, or `None` if it
+/// does not escape within `limit` iterations.
+pub fn smooth_escape<T: Float>(c: Complex<T>, limit: usize) -> Option<f64> {
+    let (count, z) = escape(c, limit, T::from(SMOOTH_RADIUS).unwrap())?;
+    let log_modulus = z.norm_sqr().to_f64().unwrap().ln() / 2.0;
+    Some(count as f64 + 1.0 - log_modulus.ln().log2())
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    v.map(|c| c / length)
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Render a rectangle of the Mandelbrot set into a buffer of pixels, lit
+/// from `light`. Points in the set are black.
+///
+/// The other arguments are the same as for `render`.
+pub fn render_lit<T: Float>(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<T>,
+    lower_right: Complex<T>,
+    limit: usize,
+    light: Light,
+) {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    let (width, height) = bounds;
+    if pixels.is_empty() {
+        return;
+    }
+
+    // Heights for the band and a ring of pixels around it.
+    let count = |n: usize| T::from(n).unwrap();
+    let pixel_width = (lower_right.re - upper_left.re) / count(width);
+    let pixel_height = (upper_left.im - lower_right.im) / count(height);
+    let mut heights = Vec::with_capacity((width + 2) * (height + 2));
+    for row in 0..height + 2 {
+        for col in 0..width + 2 {
+            let offset = |n: usize| T::from(n as f64 - 1.0).unwrap();
+            let point = Complex {
+                re: upper_left.re + offset(col) * pixel_width,
+                im: upper_left.im - offset(row) * pixel_height,
+            };
+            heights.push(smooth_escape(point, limit));
+        }
+    }
+    let at = |col: usize, row: usize| heights[row * (width + 2) + col];
+
+    let light = light.direction();
+    let halfway = normalize([light[0], light[1], light[2] + 1.0]);
+    for row in 0..height {
+        for col in 0..width {
+            let (col_h, row_h) = (col + 1, row + 1);
+            let here = match at(col_h, row_h) {
+                Some(h) => h,
+                None => {
+                    pixels[row * width + col] = 0;
+                    continue;
+                }
+            };
+            // A neighbour in the set has no height; treat it as level.
+            let h = |col: usize, row: usize| at(col, row).unwrap_or(here);
+            let dx = (h(col_h + 1, row_h) - h(col_h - 1, row_h)) / 2.0;
+            // Rows run down the image, and the y axis up it.
+            let dy = (h(col_h, row_h - 1) - h(col_h, row_h + 1)) / 2.0;
+            let slope = (dx * dx + dy * dy).sqrt();
+            let normal = if slope > 0.0 {
+                normalize([-dx / slope * RELIEF, -dy / slope * RELIEF, 1.0])
+            } else {
+                [0.0, 0.0, 1.0]
+            };
+
+            let diffuse = dot(normal, light).max(0.0);
+            let specular = dot(normal, halfway).max(0.0).powi(SHININESS);
+            let brightness = AMBIENT + DIFFUSE * diffuse + SPECULAR * specular;
+            pixels[row * width + col] = (brightness.min(1.0) * 255.0).round() as u8;
+        }
+    }
+}
+
+#[test]
+fn test_parse_light() {
+    assert_eq!(
+        "45,30".parse(),
+        Ok(Light {
+            azimuth: 45.0,
+            elevation: 30.0
+        })
+    );
+    assert!("45".parse::<Light>().is_err());
+    assert!("45,120".parse::<Light>().is_err());
+}
+
+#[test]
+fn test_smooth_escape() {
+    assert_eq!(smooth_escape(Complex::new(-1.0, 0.0), 255), None);
+    // The smoothed value rises steadily towards the set.
+    let values: Vec<f64> = [2.0, 1.0, 0.5, 0.3]
+        .iter()
+        .map(|&x| smooth_escape(Complex::new(x, 0.0), 255).unwrap())
+        .collect();
+    assert!(
+        values.windows(2).all(|pair| pair[0] < pair[1]),
+        "{:?}",
+        values
+    );
+}
+
+#[test]
+fn test_lighting_is_the_same_across_bands() {
+    let bounds = (120, 90);
+    let upper_left = Complex::new(-1.20, 0.35);
+    let lower_right = Complex::new(-1.0, 0.20);
+    let light = Light {
+        azimuth: 135.0,
+        elevation: 40.0,
+    };
+    let render = |rows_per_band| {
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        crate::render_bands(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            rows_per_band,
+            &crate::BandHooks::default(),
+            |band, band_bounds, band_upper_left, band_lower_right| {
+                render_lit(
+                    band,
+                    band_bounds,
+                    band_upper_left,
+                    band_lower_right,
+                    255,
+                    light,
+                )
+            },
+        );
+        pixels
+    };
+    let whole = render(bounds.1);
+    let banded = render(7);
+    // Bands' corners are rounded a little differently from the whole
+    // image's, which can nudge a pixel by a gray level.
+    for (a, b) in whole.iter().zip(&banded) {
+        assert!(a.abs_diff(*b) <= 1);
+    }
+
+    // Light from straight above shows no relief: every pixel outside the
+    // set is lit the same, apart from the odd flat spot facing straight up.
+    let mut overhead = vec![0; bounds.0 * bounds.1];
+    let light = Light {
+        azimuth: 0.0,
+        elevation: 90.0,
+    };
+    render_lit(&mut overhead, bounds, upper_left, lower_right, 255, light);
+    let tilted = ((AMBIENT + DIFFUSE / 2.0.sqrt()) * 255.0).round() as u8;
+    assert!(overhead
+        .iter()
+        .all(|&p| p == 0 || p.abs_diff(tilted) <= 1 || p == 255));
+}
@@ -0,0 +1,217 @@
+//! Lyapunov fractals: for each point (a, b) of the plane, drive the logistic
+//! map `x = r * x * (1 - x)` with `r` switching between `a` and `b` in a
+//! fixed sequence, and measure whether nearby orbits converge or fly apart.
+//!
+//! The measure is the Lyapunov exponent: negative where the orbit settles
+//! down, positive where it is chaotic. Points are laid out with the same
+//! viewport math as the Mandelbrot set, with `a` on the real axis and `b` on
+//! the imaginary one.
+
+use crate::{
+    invalid, io_or_exit, parse::parse_size, parsed, parsed_view, pixel_to_point, render_bands,
+    take_option, write_rgb_image, BandHooks, EXIT_USAGE,
+};
+use num::Complex;
+use std::str::FromStr;
+
+/// Iterations run before measuring, to let the orbit settle.
+const WARMUP: usize = 200;
+
+/// The number of iterations measured, unless `--iterations` says otherwise.
+const DEFAULT_ITERATIONS: usize = 1000;
+
+/// Exponents at or beyond these get the strongest colors.
+const STABLE_RANGE: f64 = 2.0;
+const CHAOS_RANGE: f64 = 1.0;
+
+/// Levels up to this one are stable; the rest are chaotic.
+const LAST_STABLE: u8 = 127;
+
+/// Parse a sequence of `A`s and `B`s into which parameter each step uses:
+/// `false` for `a`, `true` for `b`.
+pub fn parse_sequence(s: &str) -> Option<Vec<bool>> {
+    if s.is_empty() {
+        return None;
+    }
+    s.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'A' => Some(false),
+            'B' => Some(true),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The Lyapunov exponent of the logistic map at (`a`, `b`), stepping
+/// through `sequence` over and over, measured over `iterations` steps.
+pub fn exponent(a: f64, b: f64, sequence: &[bool], iterations: usize) -> f64 {
+    let mut steps = sequence
+        .iter()
+        .cycle()
+        .map(|&use_b| if use_b { b } else { a });
+    let mut x = 0.5;
+    for r in steps.by_ref().take(WARMUP) {
+        x = r * x * (1.0 - x);
+    }
+    let mut sum = 0.0;
+    for r in steps.take(iterations) {
+        x = r * x * (1.0 - x);
+        sum += (r * (1.0 - 2.0 * x)).abs().ln();
+    }
+    sum / iterations as f64
+}
+
+/// Squeeze an exponent into a level: 0 to `LAST_STABLE` for stable orbits,
+/// rising with stability, and the rest for chaotic ones, rising with chaos.
+fn level(exponent: f64) -> u8 {
+    if exponent.is_nan() || exponent < 0.0 {
+        // An orbit that hits the map's critical point exactly has an
+        // exponent of minus infinity: as stable as can be.
+        let strength = if exponent.is_nan() {
+            1.0
+        } else {
+            (-exponent / STABLE_RANGE).min(1.0)
+        };
+        (strength * LAST_STABLE as f64).round() as u8
+    } else {
+        let strength = (exponent / CHAOS_RANGE).min(1.0);
+        LAST_STABLE + 1 + (strength * (254 - LAST_STABLE) as f64).round() as u8
+    }
+}
+
+/// The color for a level: stability in gold, chaos in blue, both fading to
+/// black at the edge between them.
+fn color(level: u8) -> [u8; 3] {
+    if level <= LAST_STABLE {
+        let t = level as f64 / LAST_STABLE as f64;
+        [(255.0 * t) as u8, (200.0 * t) as u8, (40.0 * t) as u8]
+    } else {
+        let t = (level - LAST_STABLE - 1) as f64 / (254 - LAST_STABLE) as f64;
+        [(30.0 * t) as u8, (90.0 * t) as u8, (255.0 * t) as u8]
+    }
+}
+
+/// Render the levels of the Lyapunov fractal for `sequence` into `pixels`,
+/// a buffer with dimensions `bounds` covering (a, b) from `upper_left` to
+/// `lower_right`.
+pub fn render_lyapunov(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    sequence: &[bool],
+    iterations: usize,
+) {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    for row in 0..bounds.1 {
+        for col in 0..bounds.0 {
+            let point = pixel_to_point(bounds, (col, row), upper_left, lower_right);
+            pixels[row * bounds.0 + col] =
+                level(exponent(point.re, point.im, sequence, iterations));
+        }
+    }
+}
+
+/// Entry point for
+/// `mandelbrot lyapunov [--iterations N] SEQUENCE FILE PIXELS UPPERLEFT LOWERRIGHT`.
+pub fn lyapunov_main(args: &[String]) {
+    let mut args = args.to_vec();
+    let iterations = take_option(&mut args, "--iterations")
+        .map(|s| parsed(usize::from_str(&s), "iteration count"))
+        .unwrap_or(DEFAULT_ITERATIONS);
+
+    if args.len() != 7 || iterations == 0 {
+        eprintln!(
+            "Usage: {} lyapunov [--iterations N] SEQUENCE FILE PIXELS UPPERLEFT LOWERRIGHT",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} lyapunov AABAB zircon.png 800x800 2.5,4 4,2.5",
+            args[0]
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let sequence = parse_sequence(&args[2]).unwrap_or_else(|| {
+        invalid(&format!(
+            "the sequence must be made of A and B, not '{}'",
+            args[2]
+        ))
+    });
+    let bounds: (usize, usize) = parsed(parse_size(&args[4]), "image dimensions");
+    let (upper_left, lower_right) = parsed_view(&[&args[5], &args[6]], bounds);
+
+    let mut levels = vec![0; bounds.0 * bounds.1];
+    let rows_per_band = bounds.1 / num_cpus::get() + 1;
+    let failures = render_bands(
+        &mut levels,
+        bounds,
+        upper_left,
+        lower_right,
+        rows_per_band,
+        &BandHooks::default(),
+        |band, band_bounds, band_upper_left, band_lower_right| {
+            render_lyapunov(
+                band,
+                band_bounds,
+                band_upper_left,
+                band_lower_right,
+                &sequence,
+                iterations,
+            )
+        },
+    );
+
+    let pixels: Vec<u8> = levels.iter().flat_map(|&l| color(l)).collect();
+    io_or_exit(
+        write_rgb_image(&args[3], &pixels, bounds),
+        "writing PNG file",
+    );
+    if !failures.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+#[test]
+fn test_parse_sequence() {
+    assert_eq!(parse_sequence("AB"), Some(vec![false, true]));
+    assert_eq!(
+        parse_sequence("aabab"),
+        Some(vec![false, false, true, false, true])
+    );
+    assert_eq!(parse_sequence(""), None);
+    assert_eq!(parse_sequence("ABC"), None);
+}
+
+#[test]
+fn test_exponent() {
+    let a = [false];
+    // A stable fixed point, a stable 2-cycle, and chaos.
+    assert!(exponent(2.5, 0.0, &a, 1000) < 0.0);
+    assert!(exponent(3.2, 0.0, &a, 1000) < 0.0);
+    assert!(exponent(3.9, 0.0, &a, 1000) > 0.0);
+    // The superstable fixed point lands on the critical point exactly.
+    assert_eq!(exponent(2.0, 0.0, &a, 1000), f64::NEG_INFINITY);
+
+    // With "AB", b matters just as much as a.
+    let ab = [false, true];
+    assert!(exponent(2.5, 4.0, &ab, 1000) != exponent(2.5, 2.5, &ab, 1000));
+}
+
+#[test]
+fn test_level_and_color() {
+    assert_eq!(level(f64::NEG_INFINITY), LAST_STABLE);
+    assert_eq!(level(f64::NAN), LAST_STABLE);
+    assert_eq!(level(-1e-9), 0);
+    assert_eq!(level(0.0), LAST_STABLE + 1);
+    assert_eq!(level(-1.0), 64);
+    assert_eq!(level(0.5), 192);
+    assert_eq!(level(10.0), 255);
+
+    assert_eq!(color(0), [0, 0, 0]);
+    assert_eq!(color(LAST_STABLE + 1), [0, 0, 0]);
+    let [r, _, b] = color(LAST_STABLE);
+    assert!(r > b);
+    let [r, _, b] = color(255);
+    assert!(b > r);
+}
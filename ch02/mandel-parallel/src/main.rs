@@ -1,16 +1,168 @@
 use image::png::PNGEncoder;
 use image::ColorType;
-use num::Complex;
-use num_cpus;
+use num::{Complex, Float};
 use std::env;
+use std::fmt;
 use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+mod annotate;
+mod area;
+mod atlas;
+mod bench;
+mod checkpoint;
+mod control;
+mod cycle;
+mod decomposition;
+mod distributed;
+mod double_double;
+mod explore;
+mod heightmap;
+mod lighting;
+mod lyapunov;
+mod nucleus;
+mod palette;
+mod parse;
+mod precision;
+mod probe;
+mod stream;
+mod subdivide;
+
+use checkpoint::Checkpoint;
+use control::Control;
+use decomposition::Coloring;
+use lighting::Light;
+use palette::Palette;
+use parse::{parse_corners, parse_size};
+use precision::{Precision, Real};
+use stream::PngStream;
+
+/// The iteration limit used for an ordinary render.
+const DEFAULT_LIMIT: usize = 255;
+
+/// Exit statuses, after the BSD `sysexits.h` conventions: the command line
+/// was malformed, an argument didn't parse or made no sense, or a file
+/// couldn't be read or written. A render with failed bands exits with 1,
+/// and one stopped with Ctrl-C with 130.
+const EXIT_USAGE: i32 = 64;
+const EXIT_PARSE: i32 = 65;
+const EXIT_IO: i32 = 74;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
+    match args.get(1).map(String::as_str) {
+        Some("worker") => return distributed::worker_main(&args),
+        Some("coordinator") => return distributed::coordinator_main(&args),
+        Some("compare") => return precision::compare_main(&args),
+        Some("find") => return nucleus::find_main(&args),
+        Some("probe") => return probe::probe_main(&args),
+        Some("explore") => return explore::explore_main(&args),
+        Some("atlas") => return atlas::atlas_main(&args),
+        Some("lyapunov") => return lyapunov::lyapunov_main(&args),
+        Some("area") => return area::area_main(&args),
+        Some("bench") => return bench::bench_main(&args),
+        _ => {}
+    }
+
+    let mut args = args;
+    if take_flag(&mut args, "--help")
+        || take_flag(&mut args, "-h")
+        || args.get(1).is_some_and(|a| a == "help")
+    {
+        let _ = print_usage(&args[0], &mut io::stdout());
+        return;
+    }
+
     let logical_cpus = num_cpus::get(); // most common
     let physical_cpus = num_cpus::get_physical(); // available since v1.9+
+    let threads = take_option(&mut args, "--threads")
+        .map(|s| parsed(usize::from_str(&s), "thread count"))
+        .unwrap_or(logical_cpus);
+    let limit = take_option(&mut args, "--iterations")
+        .map(|s| parsed(usize::from_str(&s), "iteration limit"))
+        .unwrap_or(DEFAULT_LIMIT);
+    if let Some(output) = take_option(&mut args, "--output") {
+        args.insert(1, output);
+    }
+    let annotate = take_flag(&mut args, "--annotate");
+    let keep_partial = take_flag(&mut args, "--keep-partial");
+    let resume = take_flag(&mut args, "--resume");
+    let checkpoint_dir = take_option(&mut args, "--checkpoint");
+    let strategy = take_option(&mut args, "--strategy")
+        .map(|s| parsed(Strategy::from_str(&s), "strategy"))
+        .unwrap_or(Strategy::Pixels);
+    let light =
+        take_option(&mut args, "--light").map(|s| parsed(Light::from_str(&s), "light direction"));
+    let coloring =
+        take_option(&mut args, "--coloring").map(|s| parsed(Coloring::from_str(&s), "coloring"));
+    let strategy = match (light, coloring) {
+        (Some(_), Some(_)) => {
+            eprintln!("--light and --coloring can't be used together");
+            std::process::exit(EXIT_USAGE);
+        }
+        (Some(_), _) | (_, Some(_)) if strategy != Strategy::Pixels => {
+            eprintln!(
+                "--light and --coloring compute every pixel, and can't be used with --strategy"
+            );
+            std::process::exit(EXIT_USAGE);
+        }
+        (Some(light), None) => Strategy::Lit(light),
+        (None, Some(coloring)) => Strategy::Decomposed(coloring),
+        (None, None) => strategy,
+    };
+    let precision = take_option(&mut args, "--precision")
+        .map(|s| parsed(Precision::from_str(&s), "precision"))
+        .unwrap_or(Precision::F64);
+
+    let heightmap = take_option(&mut args, "--heightmap");
+    let mesh = take_option(&mut args, "--mesh");
+    let height_scale =
+        take_option(&mut args, "--height-scale").map(|s| parsed(f64::from_str(&s), "height scale"));
+    let mesh_step = take_option(&mut args, "--mesh-step")
+        .map(|s| parsed(usize::from_str(&s), "mesh step"))
+        .unwrap_or(1);
+    let palette =
+        take_option(&mut args, "--palette").map(|s| parsed(Palette::from_str(&s), "palette"));
+    let cycle = take_option(&mut args, "--cycle");
+    let frames = take_option(&mut args, "--frames")
+        .map(|s| parsed(usize::from_str(&s), "frame count"))
+        .unwrap_or(cycle::DEFAULT_FRAMES);
+    let smooth = take_option(&mut args, "--smooth")
+        .map(|s| parsed(usize::from_str(&s), "smoothing passes"))
+        .unwrap_or(0);
+
+    if let Some(unknown) = args.iter().find(|arg| arg.starts_with("--")) {
+        eprintln!("unknown option, or option without a value: '{}'", unknown);
+        usage_error(&args[0]);
+    }
+    if !(4..=5).contains(&args.len()) || (resume && checkpoint_dir.is_none()) {
+        usage_error(&args[0]);
+    }
+    // The heightmap and mesh are computed from the escape field afresh, but
+    // the palette is cycled through the image's own levels, which shading
+    // and decomposition give other meanings.
+    if cycle.is_some() && matches!(strategy, Strategy::Lit(_) | Strategy::Decomposed(_)) {
+        eprintln!("--cycle needs escape levels, and can't be used with --light or --coloring");
+        std::process::exit(EXIT_USAGE);
+    }
+    for (value, option) in [
+        (threads, "--threads"),
+        (limit, "--iterations"),
+        (mesh_step, "--mesh-step"),
+        (frames, "--frames"),
+    ] {
+        if value == 0 {
+            invalid(&format!("{} must be at least 1", option));
+        }
+    }
 
     println!("Logical CPUs  (what Rust threads see): {}", logical_cpus);
     println!(
@@ -18,62 +170,677 @@ fn main() {
         physical_cpus
     );
 
-    if args.len() != 5 {
-        eprintln!("Usage: {} FILE PIXELS UPPERLEFT LOWERRIGHT", args[0]);
+    let bounds = parsed(parse_size(&args[2]), "image dimensions");
+    let corners: Vec<&str> = args[3..].iter().map(String::as_str).collect();
+    let (upper_left, lower_right) = parsed_view::<f64>(&corners, bounds);
+    if let Some(filename) = &mesh {
+        if let Err(error) = heightmap::check_mesh(filename, bounds, mesh_step) {
+            invalid(&error.to_string());
+        }
+    }
+    let rows_per_band = bounds.1 / threads + 1;
+    let checkpoint = checkpoint_dir.as_ref().map(|dir| (Path::new(dir), resume));
+
+    // Unless the image needs drawing on first, encode each band as soon as
+    // it and the bands above it are done, while the rest are rendering.
+    let stream = match annotate {
+        false => Some(io_or_exit(
+            PngStream::start(&args[1], bounds, palette.as_ref()),
+            "creating PNG file",
+        )),
+        true => None,
+    };
+    let send = |top: usize, band: &[u8]| {
+        if let Some(stream) = &stream {
+            stream.send(top, band);
+        }
+    };
+
+    // The first Ctrl-C lets the slices being rendered finish, and keeps
+    // what is done; a second gives up at once.
+    let control = Arc::new(Control::new(bounds.1));
+    let handler_control = Arc::clone(&control);
+    ctrlc::set_handler(move || {
+        if handler_control.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("\nstopping; press Ctrl-C again to quit at once");
+        handler_control.cancel();
+    })
+    .expect("error setting Ctrl-C handler");
+
+    let start = Instant::now();
+    let done = AtomicBool::new(false);
+    let (pixels, failures) = thread::scope(|scope| {
+        scope.spawn(|| control.show_progress(&done));
+        let result = precision.render_image(
+            bounds,
+            &corners,
+            strategy,
+            limit,
+            rows_per_band,
+            checkpoint,
+            BandHooks {
+                finished: Some(&send),
+                control: Some(&control),
+                ..BandHooks::default()
+            },
+        );
+        done.store(true, Ordering::SeqCst);
+        result
+    });
+    let rendered = Instant::now();
+    println!("rendering took {:.3?}", rendered - start);
+
+    if control.is_cancelled() {
+        // The encoder is missing rows, so its file is no good.
+        if let Some(stream) = stream {
+            let _ = stream.finish();
+        }
+        if keep_partial {
+            io_or_exit(
+                write_image(&args[1], &pixels, bounds, palette.as_ref()),
+                "writing PNG file",
+            );
+            eprintln!(
+                "cancelled: wrote the {} of {} rows finished to {}",
+                control.rows_done(),
+                bounds.1,
+                args[1]
+            );
+        } else {
+            let _ = std::fs::remove_file(&args[1]);
+            eprintln!(
+                "cancelled after {} of {} rows",
+                control.rows_done(),
+                bounds.1
+            );
+        }
+        if checkpoint_dir.is_some() {
+            eprintln!("finished bands are checkpointed; run again with --resume to go on");
+        }
+        std::process::exit(130);
+    }
+
+    match stream {
+        Some(stream) => {
+            let timing = io_or_exit(stream.finish(), "writing PNG file");
+            println!(
+                "encoding took {:.3?}, and finished {:.3?} after rendering",
+                timing.encoding,
+                timing.finished.saturating_duration_since(rendered)
+            );
+        }
+        None => {
+            let mut annotated = pixels.clone();
+            annotate::annotate(&mut annotated, bounds, upper_left, lower_right);
+            let start = Instant::now();
+            io_or_exit(
+                write_image(&args[1], &annotated, bounds, palette.as_ref()),
+                "writing PNG file",
+            );
+            println!("encoding took {:.3?}", start.elapsed());
+        }
+    }
+
+    if let Some(filename) = cycle {
+        let start = Instant::now();
+        io_or_exit(
+            cycle::write_cycle(
+                &filename,
+                &pixels,
+                bounds,
+                frames,
+                &palette.unwrap_or_else(Palette::rainbow),
+            ),
+            "writing animation",
+        );
+        println!(
+            "{} frames of palette cycling took {:.3?}",
+            frames,
+            start.elapsed()
+        );
+    }
+
+    // Terrain needs the continuous escape field rather than the image's
+    // gray levels, so this is a second pass over the whole view.
+    if heightmap.is_some() || mesh.is_some() {
+        let mut field = precision.height_field(bounds, &corners, limit, threads);
+        field.smooth(smooth);
+        if let Some(filename) = heightmap {
+            io_or_exit(field.write_png16(&filename), "writing heightmap");
+        }
+        if let Some(filename) = mesh {
+            let scale = height_scale.unwrap_or(bounds.0 as f64 / mesh_step as f64 / 10.0);
+            let mesh = io_or_exit(field.to_mesh(scale, mesh_step), "building mesh");
+            io_or_exit(mesh.write(&filename), "writing mesh");
+        }
+    }
+
+    if !failures.is_empty() {
         eprintln!(
-            "Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
-            args[0]
+            "{} of the image's bands failed, and were left black",
+            failures.len()
         );
         std::process::exit(1);
     }
+}
 
-    let bounds = parse_pair(&args[2], 'x').expect("error parsing image dimensions");
-    let upper_left = parse_complex(&args[3]).expect("error parsing upper left corner point");
-    let lower_right = parse_complex(&args[4]).expect("error parsing lower right corner point");
+/// Write the usage message for every subcommand to `out`.
+fn print_usage(program: &str, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "Usage: {} [OPTIONS] FILE PIXELS UPPERLEFT LOWERRIGHT",
+        program
+    )?;
+    writeln!(out, "       {} [OPTIONS] FILE PIXELS CENTER@ZOOM", program)?;
+    writeln!(
+        out,
+        "       {} coordinator [--iterations N] [--timeout SECS] FILE PIXELS UPPERLEFT LOWERRIGHT WORKER...",
+        program
+    )?;
+    writeln!(out, "       {} worker ADDRESS", program)?;
+    writeln!(
+        out,
+        "       {} compare PIXELS UPPERLEFT LOWERRIGHT PRECISION PRECISION [DIFFFILE]",
+        program
+    )?;
+    writeln!(
+        out,
+        "       {} find [--preperiod Q] [--pixels WxH] PERIOD START",
+        program
+    )?;
+    writeln!(
+        out,
+        "       {} probe [--iterations N] POINT [FILE PIXELS UPPERLEFT LOWERRIGHT]",
+        program
+    )?;
+    writeln!(
+        out,
+        "       {} explore [--seed N] [--depth N] [--pixels WxH] DIR",
+        program
+    )?;
+    writeln!(
+        out,
+        "       {} atlas FILE GRID CELL UPPERLEFT LOWERRIGHT",
+        program
+    )?;
+    writeln!(
+        out,
+        "       {} lyapunov [--iterations N] SEQUENCE FILE PIXELS UPPERLEFT LOWERRIGHT",
+        program
+    )?;
+    writeln!(
+        out,
+        "       {} area [--iterations N] [--depth N] [--samples N] [--seed N]",
+        program
+    )?;
+    writeln!(
+        out,
+        "       {} bench [--pixels WxH] [--runs N] [--json]",
+        program
+    )?;
+    writeln!(out, "Options:")?;
+    writeln!(
+        out,
+        "  --output FILE                 write the image to FILE, leaving FILE out"
+    )?;
+    writeln!(
+        out,
+        "  --threads N                   render in N bands at once"
+    )?;
+    writeln!(
+        out,
+        "  --iterations N                give up on points after N iterations"
+    )?;
+    writeln!(
+        out,
+        "  --strategy pixels|subdivide   how each band is computed"
+    )?;
+    writeln!(
+        out,
+        "  --precision f32|f64|dd        floating-point type to render in"
+    )?;
+    writeln!(
+        out,
+        "  --checkpoint DIR [--resume]   save finished bands; with --resume, reuse saved ones"
+    )?;
+    writeln!(
+        out,
+        "  --keep-partial                on Ctrl-C, write the rows finished so far"
+    )?;
+    writeln!(
+        out,
+        "  --light AZIMUTH,ELEVATION     shade the image as a surface lit from there"
+    )?;
+    writeln!(
+        out,
+        "  --coloring binary|field-lines color by the angle escaping orbits leave at"
+    )?;
+    writeln!(
+        out,
+        "  --annotate                    draw axes, a grid, a scale bar and a caption"
+    )?;
+    writeln!(
+        out,
+        "  --palette {}  color the image, writing an indexed PNG",
+        palette::NAMES
+    )?;
+    writeln!(
+        out,
+        "  --cycle FILE [--frames N]     also write a palette-cycling GIF or APNG"
+    )?;
+    writeln!(
+        out,
+        "  --heightmap FILE              also write a 16-bit PNG heightmap, rendering again"
+    )?;
+    writeln!(
+        out,
+        "  --mesh FILE                   also write a terrain mesh (.obj or .stl), likewise"
+    )?;
+    writeln!(
+        out,
+        "  --height-scale N              height of the mesh's highest point"
+    )?;
+    writeln!(
+        out,
+        "  --mesh-step N                 use every Nth pixel for the mesh"
+    )?;
+    writeln!(
+        out,
+        "  --smooth N                    blur the terrain N times first"
+    )?;
+    writeln!(out, "  --help                        show this message")?;
+    writeln!(
+        out,
+        "PIXELS is WIDTHxHEIGHT or a name like 1080p or 4k; points are re,im or a+bi."
+    )?;
+    writeln!(
+        out,
+        "Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
+        program
+    )?;
+    writeln!(
+        out,
+        "         {} mandel.png 1080p -0.7436447+0.1318252i@1e4",
+        program
+    )?;
+    Ok(())
+}
+
+/// Parse the `corners` of the image in the precision `T`, and render it with `strategy` and the iteration
+/// limit `limit`, in bands of `rows_per_band` rows.
+///
+/// `checkpoint` gives a checkpoint directory and whether to resume from it,
+/// to be added to the other `hooks`. Returns the pixels and any bands that
+/// failed, as `render_bands` does.
+///
+/// The corners should already have been checked by `parsed_view` in `f64`:
+/// a view too small for `T` may round to a single point, and that is for the
+/// render to show, not an error.
+fn render_image<T: Real>(
+    bounds: (usize, usize),
+    corners: &[&str],
+    strategy: Strategy,
+    limit: usize,
+    mut rows_per_band: usize,
+    checkpoint: Option<(&Path, bool)>,
+    hooks: BandHooks,
+) -> (Vec<u8>, Vec<FailedBand>) {
+    let (upper_left, lower_right): (Complex<T>, Complex<T>) =
+        parsed(parse_corners(corners, bounds), "view");
+
+    let checkpoint = checkpoint.map(|(dir, resume)| {
+        let mut params = format!(
+            "{}x{} {},{} {},{} limit={} precision={}",
+            bounds.0,
+            bounds.1,
+            upper_left.re,
+            upper_left.im,
+            lower_right.re,
+            lower_right.im,
+            limit,
+            T::NAME
+        );
+        match strategy {
+            Strategy::Lit(light) => {
+                params += &format!(" light={},{}", light.azimuth, light.elevation)
+            }
+            Strategy::Decomposed(coloring) => params += &format!(" coloring={}", coloring),
+            Strategy::Pixels | Strategy::Subdivide => {}
+        }
+        io_or_exit(
+            Checkpoint::open(dir, &params, rows_per_band, resume),
+            "opening checkpoint directory",
+        )
+    });
+    if let Some(checkpoint) = &checkpoint {
+        rows_per_band = checkpoint.rows_per_band();
+    }
 
     let mut pixels = vec![0; bounds.0 * bounds.1];
 
-    let threads = logical_cpus;
-    let rows_per_band = bounds.1 / threads + 1;
+    for (i, band) in pixels.chunks(rows_per_band * bounds.0).enumerate() {
+        println!(
+            "band {} has {} bytes ({} pixels if RGBA)",
+            i,
+            band.len(),
+            band.len() / 4
+        );
+    }
 
-    {
-        let bands: Vec<&mut [u8]> = pixels.chunks_mut(rows_per_band * bounds.0).collect();
+    let failures = render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        rows_per_band,
+        &BandHooks {
+            checkpoint: checkpoint.as_ref(),
+            whole_bands: strategy.renders_whole_bands(),
+            ..hooks
+        },
+        |band, band_bounds, band_upper_left, band_lower_right| {
+            strategy.render(
+                band,
+                band_bounds,
+                band_upper_left,
+                band_lower_right,
+                limit,
+                hooks.control,
+            )
+        },
+    );
+    (pixels, failures)
+}
 
-        for (i, band) in bands.iter().enumerate() {
-            println!(
-                "band {} has {} bytes ({} pixels if RGBA)",
-                i,
-                band.len(),
-                band.len() / 4
-            );
+/// Remove `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) => {
+            args.remove(index);
+            true
         }
+        None => false,
+    }
+}
+
+/// Remove `option` and the value following it from `args`, returning the
+/// value. An option given without a value is left in place, so that the
+/// argument count check reports it.
+fn take_option(args: &mut Vec<String>, option: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == option)?;
+    if index + 1 >= args.len() {
+        return None;
+    }
+    args.remove(index);
+    Some(args.remove(index))
+}
 
-        crossbeam::scope(|spawner| {
-            for (i, band) in bands.into_iter().enumerate() {
-                let top = i * rows_per_band;
-                let height = band.len() / bounds.0;
-                let band_bounds = (bounds.0, height);
-                let band_upper_left = pixel_to_point(bounds, (0, top), upper_left, lower_right);
-                let band_lower_right =
-                    pixel_to_point(bounds, (bounds.0, top + height), upper_left, lower_right);
+/// How each band of the image gets its pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Strategy {
+    /// Compute the escape time of every pixel, as `render` does.
+    Pixels,
+    /// Mariani-Silver subdivision, as `subdivide::render_subdivided` does.
+    Subdivide,
+    /// Slope shading lit from the given direction, as
+    /// `lighting::render_lit` does.
+    Lit(Light),
+    /// Coloring by where escaping orbits end up, as
+    /// `decomposition::render_decomposed` does.
+    Decomposed(Coloring),
+}
 
-                spawner.spawn(move |_| {
-                    render(band, band_bounds, band_upper_left, band_lower_right);
-                });
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Strategy, String> {
+        match s {
+            "pixels" => Ok(Strategy::Pixels),
+            "subdivide" => Ok(Strategy::Subdivide),
+            _ => Err(format!("unknown strategy '{}'", s)),
+        }
+    }
+}
+
+impl Strategy {
+    /// Render a rectangle of the Mandelbrot set into `pixels` using this
+    /// strategy. The arguments are the same as for `render`, with the
+    /// `control` watching the render, for strategies that render whole bands.
+    fn render<T: Float>(
+        self,
+        pixels: &mut [u8],
+        bounds: (usize, usize),
+        upper_left: Complex<T>,
+        lower_right: Complex<T>,
+        limit: usize,
+        control: Option<&Control>,
+    ) {
+        match self {
+            Strategy::Pixels => render(pixels, bounds, upper_left, lower_right, limit),
+            Strategy::Subdivide => subdivide::render_subdivided(
+                pixels,
+                bounds,
+                upper_left,
+                lower_right,
+                limit,
+                control,
+            ),
+            Strategy::Lit(light) => {
+                lighting::render_lit(pixels, bounds, upper_left, lower_right, limit, light)
             }
-        })
-        .unwrap();
+            Strategy::Decomposed(coloring) => decomposition::render_decomposed(
+                pixels,
+                bounds,
+                upper_left,
+                lower_right,
+                limit,
+                coloring,
+            ),
+        }
     }
 
-    write_image(&args[1], &pixels, bounds).expect("error writing PNG file");
+    /// Whether this strategy wants each band whole, counting off rows and
+    /// checking for cancellation itself, rather than a slice at a time.
+    fn renders_whole_bands(self) -> bool {
+        self == Strategy::Subdivide
+    }
+}
+
+/// A function called with the top row and pixels of a band.
+type BandCallback<'a> = &'a (dyn Fn(usize, &[u8]) + Sync);
+
+/// What `render_bands` does with each band besides rendering it.
+#[derive(Clone, Copy, Default)]
+struct BandHooks<'a> {
+    /// Save each band as soon as it is finished, and load any band the
+    /// checkpoint already holds instead of rendering it.
+    checkpoint: Option<&'a Checkpoint>,
+    /// Called with the top row and pixels of each band once it is ready,
+    /// from whichever thread finished it. A band that panics is passed
+    /// along all black; one left unfinished by cancellation is not.
+    finished: Option<BandCallback<'a>>,
+    /// Render bands a slice at a time, counting off the rows and stopping
+    /// early if cancelled.
+    control: Option<&'a Control>,
+    /// With `control`, hand each band to the renderer whole instead, leaving
+    /// it to count off rows and check for cancellation. A band is counted
+    /// unfinished if the render was cancelled by the time it returned.
+    whole_bands: bool,
+}
+
+/// A band whose rendering panicked.
+#[derive(Debug)]
+struct FailedBand {
+    index: usize,
+    rows: std::ops::Range<usize>,
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    message: String,
+}
+
+impl std::fmt::Display for FailedBand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "band {} (rows {} to {}, {},{} to {},{}) failed: {}",
+            self.index,
+            self.rows.start,
+            self.rows.end - 1,
+            self.upper_left.re,
+            self.upper_left.im,
+            self.lower_right.re,
+            self.lower_right.im,
+            self.message
+        )
+    }
+}
+
+/// Render the rectangle of the Mandelbrot set given by `upper_left` and
+/// `lower_right` into `pixels`, using one thread per band of `rows_per_band`
+/// rows. Each thread calls `render_band` with its band's pixels, size and
+/// corners, and then applies `hooks` to the result.
+///
+/// A panic while rendering a band leaves that band black and is returned,
+/// rather than taking the other bands down with it.
+fn render_bands<T, F>(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<T>,
+    lower_right: Complex<T>,
+    rows_per_band: usize,
+    hooks: &BandHooks,
+    render_band: F,
+) -> Vec<FailedBand>
+where
+    T: Float + Send,
+    F: Fn(&mut [u8], (usize, usize), Complex<T>, Complex<T>) + Sync,
+{
+    let render_band = &render_band;
+    let bands: Vec<&mut [u8]> = pixels.chunks_mut(rows_per_band * bounds.0).collect();
+    let failures = std::sync::Mutex::new(Vec::new());
+    let failures_ref = &failures;
+    let corners = move |top: usize, bottom: usize| {
+        (
+            pixel_to_point(bounds, (0, top), upper_left, lower_right),
+            pixel_to_point(bounds, (bounds.0, bottom), upper_left, lower_right),
+        )
+    };
+
+    crossbeam::scope(|spawner| {
+        for (i, band) in bands.into_iter().enumerate() {
+            let top = i * rows_per_band;
+            let height = band.len() / bounds.0;
+            let band_bounds = (bounds.0, height);
+            let (band_upper_left, band_lower_right) = corners(top, top + height);
+
+            let checkpoint = hooks.checkpoint;
+            if let Some(saved) = checkpoint.and_then(|c| c.load(i, top, band.len())) {
+                band.copy_from_slice(&saved);
+                if let Some(control) = hooks.control {
+                    control.add_rows(height);
+                }
+                if let Some(finished) = hooks.finished {
+                    finished(top, band);
+                }
+                continue;
+            }
+
+            spawner.spawn(move |_| {
+                let rendered = panic::catch_unwind(AssertUnwindSafe(|| match hooks.control {
+                    None => {
+                        render_band(band, band_bounds, band_upper_left, band_lower_right);
+                        true
+                    }
+                    Some(control) if hooks.whole_bands => {
+                        render_band(band, band_bounds, band_upper_left, band_lower_right);
+                        !control.is_cancelled()
+                    }
+                    Some(control) => {
+                        for (j, slice) in
+                            band.chunks_mut(control::SLICE_ROWS * bounds.0).enumerate()
+                        {
+                            if control.is_cancelled() {
+                                return false;
+                            }
+                            let slice_top = top + j * control::SLICE_ROWS;
+                            let slice_height = slice.len() / bounds.0;
+                            let (slice_upper_left, slice_lower_right) =
+                                corners(slice_top, slice_top + slice_height);
+                            render_band(
+                                slice,
+                                (bounds.0, slice_height),
+                                slice_upper_left,
+                                slice_lower_right,
+                            );
+                            control.add_rows(slice_height);
+                        }
+                        true
+                    }
+                }));
+
+                match rendered {
+                    Ok(true) => {
+                        if let Some(checkpoint) = checkpoint {
+                            if let Err(e) = checkpoint.save(i, top, band) {
+                                eprintln!("warning: could not checkpoint band {}: {}", i, e);
+                            }
+                        }
+                    }
+                    Ok(false) => return,
+                    Err(payload) => {
+                        let message = payload
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "unknown panic".to_string());
+                        let to_f64 = |z: Complex<T>| {
+                            Complex::new(z.re.to_f64().unwrap(), z.im.to_f64().unwrap())
+                        };
+                        let failure = FailedBand {
+                            index: i,
+                            rows: top..top + height,
+                            upper_left: to_f64(band_upper_left),
+                            lower_right: to_f64(band_lower_right),
+                            message,
+                        };
+                        eprintln!("{}", failure);
+                        failures_ref.lock().unwrap().push(failure);
+                        band.fill(0);
+                    }
+                }
+                if let Some(finished) = hooks.finished {
+                    finished(top, band);
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by_key(|failure| failure.index);
+    failures
 }
 
 /// Determines whether `c` escapes to infinity within `limit` iterations.
-fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
-    let mut z = Complex { re: 0.0, im: 0.0 };
+fn escape_time<T: Float>(c: Complex<T>, limit: usize) -> Option<usize> {
+    escape(c, limit, T::from(2.0).unwrap()).map(|(count, _)| count)
+}
+
+/// Follows the orbit of `c` until it passes `radius`, which should be at
+/// least 2, and returns the number of iterations that took along with the
+/// `z` that passed it. Returns `None` if that doesn't happen within `limit`
+/// iterations.
+fn escape<T: Float>(c: Complex<T>, limit: usize, radius: T) -> Option<(usize, Complex<T>)> {
+    let radius_sqr = radius * radius;
+    let mut z = Complex {
+        re: T::zero(),
+        im: T::zero(),
+    };
     for i in 0..limit {
-        if z.norm_sqr() > 4.0 {
-            return Some(i);
+        if z.norm_sqr() > radius_sqr {
+            return Some((i, z));
         }
         z = z * z + c;
     }
@@ -82,22 +849,65 @@ fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
 }
 
 /// Parses a pair of values separated by `separator` from the string `s`.
+/// See `parse::parse_pair` for why it failed.
 fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
-    match s.find(separator) {
-        None => None,
-        Some(index) => match (T::from_str(&s[..index]), T::from_str(&s[index + 1..])) {
-            (Ok(l), Ok(r)) => Some((l, r)),
-            _ => None,
-        },
-    }
+    parse::parse_pair(s, separator).ok()
+}
+
+/// Parses a complex number written "real,imaginary" or "a+bi". Only the
+/// tests still want an `Option`; everything else reports why it failed.
+#[cfg(test)]
+fn parse_complex<T: Float + FromStr>(s: &str) -> Option<Complex<T>> {
+    parse::parse_point(s).ok()
+}
+
+/// Unwrap the parsed argument described by `what`, or explain what was
+/// wrong with it and exit.
+fn parsed<T, E: fmt::Display>(result: Result<T, E>, what: &str) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("error parsing {}: {}", what, error);
+        std::process::exit(EXIT_PARSE);
+    })
 }
 
-/// Parses a complex number from a string of the form "real,imaginary".
-fn parse_complex(s: &str) -> Option<Complex<f64>> {
-    match parse_pair::<f64>(s, ',') {
-        Some((re, im)) => Some(Complex { re, im }),
-        None => None,
+/// Parse the corners of a view of size `bounds`, as `parse_corners` does,
+/// and check that the upper left one really is above and to the left of
+/// the lower right one, or explain what was wrong and exit.
+fn parsed_view<T: Float + FromStr>(
+    corners: &[&str],
+    bounds: (usize, usize),
+) -> (Complex<T>, Complex<T>) {
+    let (upper_left, lower_right): (Complex<T>, Complex<T>) =
+        parsed(parse_corners(corners, bounds), "view");
+    if !(upper_left.re < lower_right.re && upper_left.im > lower_right.im) {
+        invalid(&format!(
+            "the upper left corner {} must be above and to the left of the lower right corner {}",
+            corners[0],
+            corners.get(1).unwrap_or(&"")
+        ));
     }
+    (upper_left, lower_right)
+}
+
+/// Report an argument that parsed, but makes no sense, and exit.
+fn invalid(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_PARSE);
+}
+
+/// Print the usage message and exit, for a malformed command line.
+fn usage_error(program: &str) -> ! {
+    let _ = print_usage(program, &mut io::stderr());
+    std::process::exit(EXIT_USAGE);
+}
+
+/// Unwrap the result of the I/O described by `what`, or report the error
+/// and exit.
+fn io_or_exit<T, E: fmt::Display>(result: Result<T, E>, what: &str) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("error {}: {}", what, error);
+        std::process::exit(EXIT_IO);
+    })
 }
 
 /// Given the row and column of a pixel in the output image, return the
@@ -107,53 +917,85 @@ fn parse_complex(s: &str) -> Option<Complex<f64>> {
 /// `pixel` is a (column, row) pair indicating a particular pixel in that image.
 /// The `upper_left` and `lower_right` parameters are points on the complex
 /// plane designating the area our image covers.
-fn pixel_to_point(
+fn pixel_to_point<T: Float>(
     bounds: (usize, usize),
     pixel: (usize, usize),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>,
-) -> Complex<f64> {
+    upper_left: Complex<T>,
+    lower_right: Complex<T>,
+) -> Complex<T> {
     let (width, height) = (
         lower_right.re - upper_left.re,
         upper_left.im - lower_right.im,
     );
+    let count = |n: usize| T::from(n).unwrap();
     Complex {
-        re: upper_left.re + (pixel.0 as f64 * width / bounds.0 as f64),
-        im: upper_left.im - (pixel.1 as f64 * height / bounds.1 as f64),
+        re: upper_left.re + (count(pixel.0) * width / count(bounds.0)),
+        im: upper_left.im - (count(pixel.1) * height / count(bounds.1)),
     }
 }
 
+/// The pixel of an image with dimensions `bounds` that covers `point`, or
+/// `None` if the point falls outside the image. This is the inverse of
+/// `pixel_to_point`, with `upper_left` and `lower_right` as there.
+fn point_to_pixel(
+    bounds: (usize, usize),
+    point: Complex<f64>,
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Option<(usize, usize)> {
+    let col = (point.re - upper_left.re) / (lower_right.re - upper_left.re) * bounds.0 as f64;
+    let row = (upper_left.im - point.im) / (upper_left.im - lower_right.im) * bounds.1 as f64;
+    // Negated comparisons so that NaN lands outside too.
+    if !(col >= 0.0 && col < bounds.0 as f64 && row >= 0.0 && row < bounds.1 as f64) {
+        return None;
+    }
+    Some((col as usize, row as usize))
+}
+
 /// Render a rectangle of the Mandelbrot set into a buffer of pixels.
 ///
 /// The `bounds` argument gives the width and height of the buffer `pixels`,
 /// which holds one grayscale pixel per byte. The `upper_left` and `lower_right`
 /// arguments specify points on the complex plane corresponding to the upper-
-/// left and lower-right corners of the pixel buffer.
-fn render(
+/// left and lower-right corners of the pixel buffer. Escape counts up to
+/// `limit` are scaled onto the full range of gray levels.
+fn render<T: Float>(
     pixels: &mut [u8],
     bounds: (usize, usize),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>,
+    upper_left: Complex<T>,
+    lower_right: Complex<T>,
+    limit: usize,
 ) {
     assert!(pixels.len() == bounds.0 * bounds.1);
 
     for row in 0..bounds.1 {
         for col in 0..bounds.0 {
             let point = pixel_to_point(bounds, (col, row), upper_left, lower_right);
-            pixels[row * bounds.0 + col] = match escape_time(point, 255) {
-                None => 0,
-                Some(count) => 255 - count as u8,
-            };
+            pixels[row * bounds.0 + col] = gray_level(escape_time(point, limit), limit);
         }
     }
 }
 
+/// The gray level `render` uses for a point that escapes after `count` of
+/// `limit` iterations: lighter the sooner it escapes, and black if it never
+/// does.
+fn gray_level(count: Option<usize>, limit: usize) -> u8 {
+    match count {
+        None => 0,
+        Some(count) => 255 - (count * 255 / limit) as u8,
+    }
+}
+
 /// Write the buffer `pixels`, whose dimensions are given by `bounds`, to the
 /// file named `filename`.
+///
+/// With a `palette`, the file is an indexed-color PNG, with each pixel's gray
+/// level as its index into the palette.
 fn write_image(
     filename: &str,
     pixels: &[u8],
     bounds: (usize, usize),
+    palette: Option<&Palette>,
 ) -> Result<(), std::io::Error> {
     // equivalent to:
     // let output = match File::create(filename) {
@@ -162,14 +1004,35 @@ fn write_image(
     //         return Err(e);
     //     }
     // };
+    let output = File::create(filename)?;
+    match palette {
+        None => {
+            let encoder = PNGEncoder::new(output);
+            encoder.encode(pixels, bounds.0 as u32, bounds.1 as u32, ColorType::Gray(8))?;
+        }
+        Some(palette) => {
+            let mut encoder =
+                png::Encoder::new(BufWriter::new(output), bounds.0 as u32, bounds.1 as u32);
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(palette.plte());
+            encoder.write_header()?.write_image_data(pixels)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the buffer `pixels`, which holds three bytes of red, green and blue
+/// for each pixel of an image with dimensions `bounds`, to the file named
+/// `filename`.
+fn write_rgb_image(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (usize, usize),
+) -> Result<(), std::io::Error> {
     let output = File::create(filename)?;
     let encoder = PNGEncoder::new(output);
-    encoder.encode(
-        &pixels,
-        bounds.0 as u32,
-        bounds.1 as u32,
-        ColorType::Gray(8),
-    )?;
+    encoder.encode(pixels, bounds.0 as u32, bounds.1 as u32, ColorType::RGB(8))?;
     Ok(())
 }
 
@@ -193,7 +1056,7 @@ fn test_parse_complex() {
         })
     );
     assert_eq!(parse_complex("0.5,0.5"), Some(Complex { re: 0.5, im: 0.5 }));
-    assert_eq!(parse_complex("1.0"), None);
+    assert_eq!(parse_complex::<f64>("1.0"), None);
 }
 
 #[test]
@@ -217,3 +1080,174 @@ fn test_pixel_to_point() {
         Complex { re: -0.5, im: 0.0 }
     );
 }
+
+#[test]
+fn test_point_to_pixel() {
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    for pixel in [(0, 0), (100, 50), (199, 99), (37, 81)] {
+        let point = pixel_to_point((200, 100), pixel, upper_left, lower_right);
+        // Aim for the middle of the pixel, away from rounding at its edges.
+        let middle = point + Complex::new(0.5 * 3.0 / 200.0, -0.5 * 2.0 / 100.0);
+        assert_eq!(
+            point_to_pixel((200, 100), middle, upper_left, lower_right),
+            Some(pixel)
+        );
+    }
+    let outside = [
+        Complex::new(-2.5, 0.0),
+        Complex::new(1.0, 0.0),
+        Complex::new(0.0, 1.5),
+        Complex::new(0.0, -1.0),
+        Complex::new(f64::NAN, 0.0),
+    ];
+    for point in outside {
+        assert_eq!(
+            point_to_pixel((200, 100), point, upper_left, lower_right),
+            None
+        );
+    }
+}
+
+#[test]
+fn test_resume_matches_uninterrupted_render() {
+    let bounds = (60, 45);
+    let (upper_left, lower_right) = (
+        Complex {
+            re: -1.20,
+            im: 0.35,
+        },
+        Complex { re: -1.0, im: 0.20 },
+    );
+    let dir = std::env::temp_dir().join(format!("mandel-resume-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let render_band = |band: &mut [u8], band_bounds, band_upper_left, band_lower_right| {
+        render(band, band_bounds, band_upper_left, band_lower_right, 255)
+    };
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    render_bands(
+        &mut expected,
+        bounds,
+        upper_left,
+        lower_right,
+        8,
+        &BandHooks::default(),
+        render_band,
+    );
+
+    let open = |resume| Checkpoint::open(&dir, "resume test", 8, resume).unwrap();
+    let mut first = vec![0; bounds.0 * bounds.1];
+    render_bands(
+        &mut first,
+        bounds,
+        upper_left,
+        lower_right,
+        8,
+        &BandHooks {
+            checkpoint: Some(&open(false)),
+            ..BandHooks::default()
+        },
+        render_band,
+    );
+    assert_eq!(first, expected);
+
+    // Lose two bands, and plant a marker in a third to show it is reused.
+    std::fs::remove_file(dir.join("band-0001")).unwrap();
+    std::fs::remove_file(dir.join("band-0004")).unwrap();
+    let checkpoint = open(true);
+    let mut marked = expected[16 * 60..24 * 60].to_vec();
+    marked[0] = 7;
+    checkpoint.save(2, 16, &marked).unwrap();
+
+    let mut resumed = vec![0; bounds.0 * bounds.1];
+    render_bands(
+        &mut resumed,
+        bounds,
+        upper_left,
+        lower_right,
+        8,
+        &BandHooks {
+            checkpoint: Some(&checkpoint),
+            ..BandHooks::default()
+        },
+        render_band,
+    );
+    assert_eq!(resumed[16 * 60], 7);
+    resumed[16 * 60] = expected[16 * 60];
+    assert_eq!(resumed, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_panicking_band_is_isolated() {
+    let bounds = (30, 40);
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+    let failures = render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        10,
+        &BandHooks::default(),
+        |band, band_bounds, band_upper_left: Complex<f64>, band_lower_right| {
+            // The third band, rows 20 to 29, is the one whose top is at 0.
+            if band_upper_left.im == 0.0 {
+                panic!("bad band");
+            }
+            render(band, band_bounds, band_upper_left, band_lower_right, 255);
+        },
+    );
+
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].index, 2);
+    assert_eq!(failures[0].rows, 20..30);
+    assert_eq!(failures[0].message, "bad band");
+    assert!(failures[0]
+        .to_string()
+        .starts_with("band 2 (rows 20 to 29, -2,0 to 1,-0.5)"));
+
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    render(&mut expected, bounds, upper_left, lower_right, 255);
+    expected[20 * 30..30 * 30].fill(0);
+    assert_eq!(pixels, expected);
+}
+
+#[test]
+fn test_cancelled_render_stops_between_slices() {
+    let bounds = (20, 100);
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    let control = Control::new(bounds.1);
+    let finished = std::sync::Mutex::new(Vec::new());
+    let record = |top: usize, _: &[u8]| finished.lock().unwrap().push(top);
+    let mut pixels = vec![7; bounds.0 * bounds.1];
+
+    // One band, which cancels the render after its first slice.
+    render_bands(
+        &mut pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        bounds.1,
+        &BandHooks {
+            finished: Some(&record),
+            control: Some(&control),
+            ..BandHooks::default()
+        },
+        |band, band_bounds, band_upper_left, band_lower_right| {
+            render(band, band_bounds, band_upper_left, band_lower_right, 255);
+            control.cancel();
+        },
+    );
+
+    let slice = control::SLICE_ROWS * bounds.0;
+    assert_eq!(control.rows_done(), control::SLICE_ROWS);
+    assert!(pixels[..slice].iter().all(|&p| p != 7));
+    assert!(pixels[slice..].iter().all(|&p| p == 7));
+    // An unfinished band is not passed on.
+    assert!(finished.lock().unwrap().is_empty());
+}
@@ -0,0 +1,295 @@
+//! Finding the interesting points of the Mandelbrot set by Newton's method,
+//! so that a render can be aimed straight at them.
+//!
+//! The nucleus of a hyperbolic component of period `p` is the `c` for which
+//! zero is periodic with period `p`: the `p`th iterate of zero is zero
+//! again. Every minibrot has one at the center of its cardioid. A
+//! Misiurewicz point `M(q, p)` is a `c` for which zero is strictly
+//! preperiodic: after `q` iterations the orbit lands on a cycle of period
+//! `p`. These are the tips and branch points of the filaments.
+
+use crate::parse::{parse_point, parse_size, viewport};
+use crate::{invalid, parsed, take_option, EXIT_USAGE};
+use num::Complex;
+use std::str::FromStr;
+
+/// Give up on Newton's method after this many steps.
+const MAX_STEPS: usize = 100;
+
+/// Consider Newton's method converged once a step moves `c` by less than
+/// this, relative to the size of `c`.
+const TOLERANCE: f64 = 1e-15;
+
+/// A point found by `find_nucleus` or `find_misiurewicz`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Found {
+    pub c: Complex<f64>,
+    pub steps: usize,
+}
+
+/// Iterate `z = z * z + c` from zero `n` times, returning every `z` along
+/// the way, starting with zero itself, and its derivative with respect to
+/// `c`.
+fn orbit(c: Complex<f64>, n: usize) -> Vec<(Complex<f64>, Complex<f64>)> {
+    let mut points = vec![(Complex::new(0.0, 0.0), Complex::new(0.0, 0.0))];
+    for _ in 0..n {
+        let (z, dz) = *points.last().unwrap();
+        points.push((z * z + c, 2.0 * z * dz + 1.0));
+    }
+    points
+}
+
+/// Run Newton's method from `start`, where `ratio` gives the logarithmic
+/// derivative `f'(c) / f(c)` of the function whose root we want.
+fn newton(start: Complex<f64>, ratio: impl Fn(Complex<f64>) -> Complex<f64>) -> Option<Found> {
+    let mut c = start;
+    for steps in 1..=MAX_STEPS {
+        let ratio = ratio(c);
+        if !ratio.re.is_finite() || !ratio.im.is_finite() {
+            // `f(c)` is exactly zero: we have landed right on the root.
+            return Some(Found { c, steps });
+        }
+        let next = c - 1.0 / ratio;
+        if !next.re.is_finite() || !next.im.is_finite() {
+            return None;
+        }
+        let moved = (next - c).norm();
+        c = next;
+        if moved <= TOLERANCE * c.norm().max(1.0) {
+            return Some(Found { c, steps });
+        }
+    }
+    None
+}
+
+/// Find the nucleus of a hyperbolic component of period `period` near
+/// `start`.
+///
+/// The `period`th iterate of zero also vanishes at the nuclei of every
+/// period dividing `period`, so those roots are divided out first to keep
+/// Newton's method from settling on them.
+pub fn find_nucleus(period: usize, start: Complex<f64>) -> Option<Found> {
+    newton(start, |c| {
+        let points = orbit(c, period);
+        let (z, dz) = points[period];
+        let mut ratio = dz / z;
+        for divisor in (1..period).filter(|&d| period.is_multiple_of(d)) {
+            let (z, dz) = points[divisor];
+            ratio -= dz / z;
+        }
+        ratio
+    })
+}
+
+/// Find the Misiurewicz point with preperiod `preperiod` and period
+/// `period` near `start`.
+///
+/// We solve `z[q + p] = z[q]`, dividing out the roots of `z[i + p] = z[i]`
+/// for each smaller `i`, which are the points with a shorter preperiod.
+pub fn find_misiurewicz(preperiod: usize, period: usize, start: Complex<f64>) -> Option<Found> {
+    newton(start, |c| {
+        let points = orbit(c, preperiod + period);
+        let difference = |i: usize| {
+            let ((z_i, dz_i), (z_ip, dz_ip)) = (points[i], points[i + period]);
+            (dz_ip - dz_i) / (z_ip - z_i)
+        };
+        let mut ratio = difference(preperiod);
+        for i in 0..preperiod {
+            ratio -= difference(i);
+        }
+        ratio
+    })
+}
+
+/// The smallest period of the orbit of zero under `z = z * z + c`, if it
+/// returns to zero within `limit` iterations.
+pub fn exact_period(c: Complex<f64>, limit: usize) -> Option<usize> {
+    let mut z = Complex::new(0.0, 0.0);
+    for n in 1..=limit {
+        z = z * z + c;
+        if z.norm() < 1e-9 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// The smallest preperiod and period of the orbit of zero at `c`, searching
+/// preperiods and periods up to `limit`.
+pub fn exact_preperiod(c: Complex<f64>, limit: usize) -> Option<(usize, usize)> {
+    let mut orbit = vec![Complex::new(0.0, 0.0)];
+    for _ in 0..2 * limit {
+        let z = *orbit.last().unwrap();
+        orbit.push(z * z + c);
+    }
+    let scale = orbit.iter().map(|z| z.norm()).fold(1.0, f64::max);
+    (0..=limit)
+        .flat_map(|q| (1..=limit).map(move |p| (q, p)))
+        .find(|&(q, p)| (orbit[q + p] - orbit[q]).norm() < 1e-9 * scale)
+}
+
+/// Estimate the size of the hyperbolic component of period `period` whose
+/// nucleus is `c`. The magnitude is comparable to the width of the
+/// component's cardioid or disk, and the argument gives its orientation.
+///
+/// The estimate comes from linearizing the `period`th iterate near the
+/// nucleus, where the component looks like a scaled copy of the whole set.
+pub fn component_size(c: Complex<f64>, period: usize) -> Complex<f64> {
+    let mut z = Complex::new(0.0, 0.0);
+    let mut l = Complex::new(1.0, 0.0);
+    let mut b = Complex::new(1.0, 0.0);
+    for _ in 1..period {
+        z = z * z + c;
+        l = 2.0 * z * l;
+        b += 1.0 / l;
+    }
+    1.0 / (b * l * l)
+}
+
+/// A rough scale for the structure around the Misiurewicz point `c`: how
+/// far `c` must move for the preperiodic part of its orbit to move by about
+/// the size of the set.
+pub fn misiurewicz_scale(c: Complex<f64>, preperiod: usize) -> f64 {
+    let (_, dz) = orbit(c, preperiod)[preperiod];
+    2.0 / dz.norm()
+}
+
+/// Entry point for `mandelbrot find [--preperiod Q] [--pixels WxH] PERIOD START`.
+pub fn find_main(args: &[String]) {
+    let mut args = args.to_vec();
+    let preperiod =
+        take_option(&mut args, "--preperiod").map(|s| parsed(usize::from_str(&s), "preperiod"));
+    let bounds = take_option(&mut args, "--pixels")
+        .map(|s| parsed(parse_size(&s), "image dimensions"))
+        .unwrap_or((1000, 750));
+
+    if args.len() != 4 {
+        eprintln!(
+            "Usage: {} find [--preperiod Q] [--pixels WxH] PERIOD START",
+            args[0]
+        );
+        eprintln!("Example: {} find 3 -1.75,0", args[0]);
+        eprintln!("         {} find --preperiod 3 1 -0.1,1", args[0]);
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let period = parsed(usize::from_str(&args[2]), "period");
+    let start = parsed(parse_point(&args[3]), "starting point");
+    if period == 0 {
+        invalid("period must be at least 1");
+    }
+    if preperiod == Some(0) {
+        invalid("--preperiod must be at least 1");
+    }
+
+    let (found, width) = match preperiod {
+        None => {
+            let found = find_nucleus(period, start).unwrap_or_else(|| {
+                eprintln!("Newton's method did not converge from {}", start);
+                std::process::exit(1);
+            });
+            println!(
+                "nucleus of period {}: {},{}",
+                period, found.c.re, found.c.im
+            );
+            let actual = exact_period(found.c, period);
+            match actual {
+                Some(actual) if actual != period => println!(
+                    "  warning: this is a nucleus of period {}, which divides {}",
+                    actual, period
+                ),
+                None => println!("  warning: zero is not periodic here; try another start"),
+                _ => {}
+            }
+            let size = component_size(found.c, actual.unwrap_or(period));
+            println!(
+                "  size estimate: {:e} (oriented at {:.1} degrees)",
+                size.norm(),
+                size.arg().to_degrees()
+            );
+            (found, 4.0 * size.norm())
+        }
+        Some(preperiod) => {
+            let found = find_misiurewicz(preperiod, period, start).unwrap_or_else(|| {
+                eprintln!("Newton's method did not converge from {}", start);
+                std::process::exit(1);
+            });
+            println!(
+                "Misiurewicz point M({},{}): {},{}",
+                preperiod, period, found.c.re, found.c.im
+            );
+            match exact_preperiod(found.c, preperiod + period) {
+                Some((q, p)) if (q, p) != (preperiod, period) => {
+                    println!("  warning: this point is actually M({},{})", q, p)
+                }
+                _ => {}
+            }
+            let scale = misiurewicz_scale(found.c, preperiod);
+            println!("  scale estimate: {:e}", scale);
+            (found, 2.0 * scale)
+        }
+    };
+
+    println!("  Newton steps: {}", found.steps);
+    let (upper_left, lower_right) = viewport(found.c, width, bounds);
+    println!(
+        "  suggested viewport for {}x{}: {},{} {},{}",
+        bounds.0, bounds.1, upper_left.re, upper_left.im, lower_right.re, lower_right.im
+    );
+}
+
+#[test]
+fn test_find_nucleus() {
+    let near = |found: Option<Found>, re: f64, im: f64| {
+        let c = found.unwrap().c;
+        (c - Complex::new(re, im)).norm() < 1e-12
+    };
+    assert!(near(find_nucleus(1, Complex::new(0.1, 0.1)), 0.0, 0.0));
+    assert!(near(find_nucleus(2, Complex::new(-0.9, 0.05)), -1.0, 0.0));
+    // Starting by the period-1 nucleus still finds period 2.
+    assert!(near(find_nucleus(2, Complex::new(-0.1, 0.0)), -1.0, 0.0));
+    assert!(near(
+        find_nucleus(3, Complex::new(-1.75, 0.0)),
+        -1.7548776662466927,
+        0.0
+    ));
+    assert!(near(
+        find_nucleus(3, Complex::new(-0.1, 0.8)),
+        -0.12256116687665361,
+        0.7448617666197442
+    ));
+}
+
+#[test]
+fn test_find_misiurewicz() {
+    // The tip of the antenna: 0 -> -2 -> 2 -> 2 -> ...
+    let tip = find_misiurewicz(2, 1, Complex::new(-1.9, 0.0)).unwrap();
+    assert!((tip.c - Complex::new(-2.0, 0.0)).norm() < 1e-12);
+    assert_eq!(exact_preperiod(tip.c, 5), Some((2, 1)));
+
+    // 0 -> i -> -1 + i -> -i -> -1 + i -> ...
+    let i = find_misiurewicz(2, 2, Complex::new(0.05, 0.95)).unwrap();
+    assert!((i.c - Complex::new(0.0, 1.0)).norm() < 1e-12);
+    assert_eq!(exact_preperiod(i.c, 5), Some((2, 2)));
+
+    // Starting right by M(1,1) at zero still finds a point of preperiod 3.
+    let found = find_misiurewicz(3, 1, Complex::new(-0.1, 1.0)).unwrap();
+    assert_eq!(exact_preperiod(found.c, 5), Some((3, 1)));
+}
+
+#[test]
+fn test_component_size() {
+    // The main cardioid, and the period-2 disk of radius 1/4.
+    assert_eq!(
+        component_size(Complex::new(0.0, 0.0), 1),
+        Complex::new(1.0, 0.0)
+    );
+    assert_eq!(
+        component_size(Complex::new(-1.0, 0.0), 2),
+        Complex::new(0.5, 0.0)
+    );
+
+    let c = find_nucleus(3, Complex::new(-1.75, 0.0)).unwrap().c;
+    let size = component_size(c, 3).norm();
+    assert!(size > 0.01 && size < 0.03);
+}
@@ -0,0 +1,328 @@
+//! Choosing the floating-point type the renderer works in, and comparing
+//! the images different choices produce.
+//!
+//! `f32` is fastest but runs out of precision after a few zooms; `f64`
+//! lasts to a width of about 1e-13; double-double goes some fifteen orders
+//! of magnitude further at a steep cost in speed. `compare` renders a view
+//! in two precisions and reports where the images disagree, which shows
+//! whether the cheaper one is good enough for that view.
+
+use crate::double_double::DoubleDouble;
+use crate::heightmap::HeightField;
+use crate::parse::{parse_corners, parse_size};
+use crate::{
+    io_or_exit, parsed, parsed_view, pixel_to_point, render_image, write_image, BandHooks,
+    FailedBand, Strategy, DEFAULT_LIMIT, EXIT_USAGE,
+};
+use num::{Complex, Float};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A floating-point type the renderer can work in.
+pub trait Real: Float + FromStr + fmt::Display + Send + Sync + 'static {
+    /// The name `--precision` knows this type by.
+    const NAME: &'static str;
+}
+
+impl Real for f32 {
+    const NAME: &'static str = "f32";
+}
+
+impl Real for f64 {
+    const NAME: &'static str = "f64";
+}
+
+impl Real for DoubleDouble {
+    const NAME: &'static str = "dd";
+}
+
+/// The precisions selectable with `--precision`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    F32,
+    F64,
+    DoubleDouble,
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Precision, String> {
+        match s {
+            "f32" => Ok(Precision::F32),
+            "f64" => Ok(Precision::F64),
+            "dd" => Ok(Precision::DoubleDouble),
+            _ => Err(format!("unknown precision '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Precision::F32 => f32::NAME,
+            Precision::F64 => f64::NAME,
+            Precision::DoubleDouble => DoubleDouble::NAME,
+        })
+    }
+}
+
+impl Precision {
+    /// Render an image in this precision. The arguments are the same as for
+    /// `render_image`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_image(
+        self,
+        bounds: (usize, usize),
+        corners: &[&str],
+        strategy: Strategy,
+        limit: usize,
+        rows_per_band: usize,
+        checkpoint: Option<(&Path, bool)>,
+        hooks: BandHooks,
+    ) -> (Vec<u8>, Vec<FailedBand>) {
+        match self {
+            Precision::F32 => render_image::<f32>(
+                bounds,
+                corners,
+                strategy,
+                limit,
+                rows_per_band,
+                checkpoint,
+                hooks,
+            ),
+            Precision::F64 => render_image::<f64>(
+                bounds,
+                corners,
+                strategy,
+                limit,
+                rows_per_band,
+                checkpoint,
+                hooks,
+            ),
+            Precision::DoubleDouble => render_image::<DoubleDouble>(
+                bounds,
+                corners,
+                strategy,
+                limit,
+                rows_per_band,
+                checkpoint,
+                hooks,
+            ),
+        }
+    }
+
+    /// Compute the height field of the view given by `corners` in this
+    /// precision, as `HeightField::from_escape` does.
+    pub fn height_field(
+        self,
+        bounds: (usize, usize),
+        corners: &[&str],
+        limit: usize,
+        threads: usize,
+    ) -> HeightField {
+        fn field<T: Real>(
+            bounds: (usize, usize),
+            corners: &[&str],
+            limit: usize,
+            threads: usize,
+        ) -> HeightField {
+            let (upper_left, lower_right): (Complex<T>, Complex<T>) =
+                parsed(parse_corners(corners, bounds), "view");
+            HeightField::from_escape(bounds, upper_left, lower_right, limit, threads)
+        }
+        match self {
+            Precision::F32 => field::<f32>(bounds, corners, limit, threads),
+            Precision::F64 => field::<f64>(bounds, corners, limit, threads),
+            Precision::DoubleDouble => field::<DoubleDouble>(bounds, corners, limit, threads),
+        }
+    }
+}
+
+/// A pixel on which two renders disagree.
+#[derive(Debug, PartialEq)]
+pub struct Difference {
+    pub pixel: (usize, usize),
+    pub first: u8,
+    pub second: u8,
+}
+
+/// List the pixels at which `first` and `second`, both images of size
+/// `bounds`, differ.
+pub fn differences(first: &[u8], second: &[u8], bounds: (usize, usize)) -> Vec<Difference> {
+    assert!(first.len() == bounds.0 * bounds.1 && second.len() == first.len());
+    first
+        .iter()
+        .zip(second)
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(i, (&first, &second))| Difference {
+            pixel: (i % bounds.0, i / bounds.0),
+            first,
+            second,
+        })
+        .collect()
+}
+
+/// How many tiles across and down `compare` summarizes the differences in.
+const SUMMARY_TILES: usize = 8;
+
+/// Entry point for
+/// `mandelbrot compare PIXELS UPPERLEFT LOWERRIGHT PRECISION PRECISION [DIFFFILE]`.
+///
+/// Prints how many pixels differ, a map of where they are, and the first few
+/// of them. With DIFFFILE, also writes an image that is white wherever the
+/// two renders differ.
+pub fn compare_main(args: &[String]) {
+    if args.len() != 7 && args.len() != 8 {
+        eprintln!(
+            "Usage: {} compare PIXELS UPPERLEFT LOWERRIGHT PRECISION PRECISION [DIFFFILE]",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} compare 800x600 -0.7436447,0.1318254 -0.7436437,0.1318246 f32 f64 diff.png",
+            args[0]
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let bounds: (usize, usize) = parsed(parse_size(&args[2]), "image dimensions");
+    let corners = [args[3].as_str(), args[4].as_str()];
+    let (upper_left, lower_right) = parsed_view::<f64>(&corners, bounds);
+    let first = parsed(Precision::from_str(&args[5]), "first precision");
+    let second = parsed(Precision::from_str(&args[6]), "second precision");
+
+    let rows_per_band = bounds.1 / num_cpus::get() + 1;
+    let render = |precision: Precision| {
+        precision
+            .render_image(
+                bounds,
+                &corners,
+                Strategy::Pixels,
+                DEFAULT_LIMIT,
+                rows_per_band,
+                None,
+                BandHooks::default(),
+            )
+            .0
+    };
+    let (first_pixels, second_pixels) = (render(first), render(second));
+    let diffs = differences(&first_pixels, &second_pixels, bounds);
+
+    let total = bounds.0 * bounds.1;
+    let largest = diffs
+        .iter()
+        .map(|d| d.first.abs_diff(d.second))
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{} vs {}: {} of {} pixels differ ({:.3}%), largest difference {} gray levels",
+        first,
+        second,
+        diffs.len(),
+        total,
+        100.0 * diffs.len() as f64 / total.max(1) as f64,
+        largest
+    );
+
+    if !diffs.is_empty() {
+        println!("percentage of differing pixels in each tile of the image:");
+        let tile = (
+            bounds.0.div_ceil(SUMMARY_TILES),
+            bounds.1.div_ceil(SUMMARY_TILES),
+        );
+        let mut counts = [[0usize; SUMMARY_TILES]; SUMMARY_TILES];
+        for d in &diffs {
+            counts[d.pixel.1 / tile.1][d.pixel.0 / tile.0] += 1;
+        }
+        for row in &counts {
+            let line: Vec<String> = row
+                .iter()
+                .map(|&n| format!("{:6.1}", 100.0 * n as f64 / (tile.0 * tile.1) as f64))
+                .collect();
+            println!("  {}", line.join(""));
+        }
+
+        println!(
+            "first differing pixels (column, row, point, {}, {}):",
+            first, second
+        );
+        for d in diffs.iter().take(10) {
+            let point = pixel_to_point(bounds, d.pixel, upper_left, lower_right);
+            println!(
+                "  {:5} {:5}  {},{}  {:3} {:3}",
+                d.pixel.0, d.pixel.1, point.re, point.im, d.first, d.second
+            );
+        }
+    }
+
+    if let Some(filename) = args.get(7) {
+        let mut mask = vec![0; total];
+        for d in &diffs {
+            mask[d.pixel.1 * bounds.0 + d.pixel.0] = 255;
+        }
+        io_or_exit(
+            write_image(filename, &mask, bounds, None),
+            "writing PNG file",
+        );
+    }
+}
+
+#[test]
+fn test_parse_precision() {
+    assert_eq!("f32".parse(), Ok(Precision::F32));
+    assert_eq!("dd".parse(), Ok(Precision::DoubleDouble));
+    assert!("f16".parse::<Precision>().is_err());
+    assert_eq!(Precision::DoubleDouble.to_string(), "dd");
+}
+
+#[test]
+fn test_differences() {
+    let first = [1, 2, 3, 4, 5, 6];
+    let second = [1, 2, 0, 4, 5, 9];
+    assert_eq!(
+        differences(&first, &second, (3, 2)),
+        vec![
+            Difference {
+                pixel: (2, 0),
+                first: 3,
+                second: 0
+            },
+            Difference {
+                pixel: (2, 1),
+                first: 6,
+                second: 9
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_precisions_diverge_when_zoomed_in() {
+    // A view 1e-9 wide: far too small for f32, comfortable for f64.
+    let bounds = (40, 30);
+    let corners = ["-1.7687788345,-0.0017389955", "-1.7687788335,-0.0017389965"];
+    let render = |precision: Precision| {
+        precision
+            .render_image(
+                bounds,
+                &corners,
+                Strategy::Pixels,
+                DEFAULT_LIMIT,
+                10,
+                None,
+                BandHooks::default(),
+            )
+            .0
+    };
+    let (f32_pixels, f64_pixels, dd_pixels) = (
+        render(Precision::F32),
+        render(Precision::F64),
+        render(Precision::DoubleDouble),
+    );
+
+    let total = bounds.0 * bounds.1;
+    assert!(differences(&f32_pixels, &f64_pixels, bounds).len() > total / 4);
+    assert!(differences(&f64_pixels, &dd_pixels, bounds).len() < total / 20);
+}
@@ -0,0 +1,263 @@
+//! Inspecting a single point: its whole orbit, and the numbers the renderer
+//! would derive from it. Useful for working out why one pixel of a render
+//! looks wrong.
+
+use crate::{
+    escape_time, gray_level, io_or_exit,
+    parse::{parse_point, parse_size},
+    parsed, parsed_view, point_to_pixel, render, render_bands, take_option, write_rgb_image,
+    BandHooks, DEFAULT_LIMIT, EXIT_USAGE,
+};
+use num::Complex;
+use std::str::FromStr;
+
+/// Consider the orbit periodic once it comes back this close to where it
+/// was, relative to its size.
+const PERIOD_TOLERANCE: f64 = 1e-9;
+
+/// Escaping orbits are followed out to this radius for the distance
+/// estimate, which is only accurate far from the set.
+const ESCAPE_RADIUS: f64 = 1e10;
+
+/// The orbit of zero under `z = z * z + c`, up to and including the first
+/// point outside the escape radius of 2, and at most `limit` points long.
+pub fn orbit(c: Complex<f64>, limit: usize) -> Vec<Complex<f64>> {
+    let mut z = Complex::new(0.0, 0.0);
+    let mut points = Vec::new();
+    for _ in 0..limit {
+        points.push(z);
+        if z.norm_sqr() > 4.0 {
+            break;
+        }
+        z = z * z + c;
+    }
+    points
+}
+
+/// The period of the cycle the orbit of zero settles into, if `limit`
+/// iterations are enough for it to settle and it repeats within another
+/// `limit`.
+pub fn period(c: Complex<f64>, limit: usize) -> Option<usize> {
+    let mut z = Complex::new(0.0, 0.0);
+    for _ in 0..limit {
+        z = z * z + c;
+        if z.norm_sqr() > 4.0 {
+            return None;
+        }
+    }
+    let start = z;
+    for n in 1..=limit {
+        z = z * z + c;
+        if (z - start).norm() < PERIOD_TOLERANCE * start.norm().max(1.0) {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// Estimate the distance from `c` to the Mandelbrot set, if `c` escapes
+/// within `limit` iterations. It is within a factor of about four of the
+/// true distance either way.
+pub fn distance_estimate(c: Complex<f64>, limit: usize) -> Option<f64> {
+    let mut z = Complex::new(0.0, 0.0);
+    let mut dz = Complex::new(0.0, 0.0);
+    for _ in 0..limit {
+        if z.norm() > ESCAPE_RADIUS {
+            let r = z.norm();
+            return Some(2.0 * r * r.ln() / dz.norm());
+        }
+        dz = 2.0 * z * dz + 1.0;
+        z = z * z + c;
+    }
+    None
+}
+
+/// True if `c` lies in the main cardioid, where zero settles on a fixed
+/// point.
+pub fn in_main_cardioid(c: Complex<f64>) -> bool {
+    let x = c.re - 0.25;
+    let q = x * x + c.im * c.im;
+    q * (q + x) <= c.im * c.im / 4.0
+}
+
+/// Colors for the orbit overlay.
+const LINE_COLOR: [u8; 3] = [255, 0, 0];
+const POINT_COLOR: [u8; 3] = [255, 255, 0];
+const START_COLOR: [u8; 3] = [0, 255, 0];
+
+/// An RGB copy of a grayscale render, for drawing an orbit on.
+struct Overlay {
+    pixels: Vec<u8>,
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+}
+
+impl Overlay {
+    fn plot(&mut self, point: Complex<f64>, color: [u8; 3]) {
+        if let Some((col, row)) =
+            point_to_pixel(self.bounds, point, self.upper_left, self.lower_right)
+        {
+            let start = 3 * (row * self.bounds.0 + col);
+            self.pixels[start..start + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Plot `point` and the pixels around it.
+    fn mark(&mut self, point: Complex<f64>, color: [u8; 3]) {
+        let pixel = Complex::new(
+            (self.lower_right.re - self.upper_left.re) / self.bounds.0 as f64,
+            (self.upper_left.im - self.lower_right.im) / self.bounds.1 as f64,
+        );
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let offset = Complex::new(dx as f64 * pixel.re, dy as f64 * pixel.im);
+                self.plot(point + offset, color);
+            }
+        }
+    }
+
+    /// Draw a straight line from `from` to `to`, clipped to the image.
+    fn line(&mut self, from: Complex<f64>, to: Complex<f64>, color: [u8; 3]) {
+        let pixel_size = ((self.lower_right.re - self.upper_left.re) / self.bounds.0 as f64)
+            .min((self.upper_left.im - self.lower_right.im) / self.bounds.1 as f64);
+        // Escaping orbits leap a long way out; there is nothing to see there.
+        let steps = ((to - from).norm() / pixel_size).ceil().min(1e5) as usize;
+        for i in 0..=steps {
+            let t = i as f64 / steps.max(1) as f64;
+            self.plot(from + (to - from) * t, color);
+        }
+    }
+}
+
+/// Entry point for `mandelbrot probe [--iterations N] POINT [FILE PIXELS UPPERLEFT LOWERRIGHT]`.
+///
+/// Prints the orbit of POINT and what it tells us. With FILE and the rest,
+/// also renders that view and draws the orbit over it.
+pub fn probe_main(args: &[String]) {
+    let mut args = args.to_vec();
+    let limit = take_option(&mut args, "--iterations")
+        .map(|s| parsed(usize::from_str(&s), "iteration limit"))
+        .unwrap_or(DEFAULT_LIMIT);
+
+    if (args.len() != 3 && args.len() != 7) || limit == 0 {
+        eprintln!(
+            "Usage: {} probe [--iterations N] POINT [FILE PIXELS UPPERLEFT LOWERRIGHT]",
+            args[0]
+        );
+        eprintln!(
+            "Example: {} probe -0.75,0.1 orbit.png 1000x750 -2,1.2 1,-1.2",
+            args[0]
+        );
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let c: Complex<f64> = parsed(parse_point(&args[2]), "point");
+    let points = orbit(c, limit);
+
+    println!("orbit of zero at {},{}:", c.re, c.im);
+    for (n, z) in points.iter().enumerate() {
+        println!("  {:5}  {:>24} {:>24}  |z| = {}", n, z.re, z.im, z.norm());
+    }
+
+    let escape = escape_time(c, limit);
+    match escape {
+        Some(n) => println!("escapes after {} of {} iterations", n, limit),
+        None => println!("does not escape within {} iterations", limit),
+    }
+    println!("gray level: {}", gray_level(escape, limit));
+    match period(c, limit) {
+        Some(p) => println!("period: {}", p),
+        None if escape.is_none() => println!("period: none found within {} iterations", limit),
+        None => {}
+    }
+    match distance_estimate(c, limit + 64) {
+        Some(d) => println!("distance estimate: {:e}", d),
+        None => println!("distance estimate: none, the point does not escape"),
+    }
+    println!(
+        "in main cardioid: {}",
+        if in_main_cardioid(c) { "yes" } else { "no" }
+    );
+
+    if args.len() == 7 {
+        let bounds = parsed(parse_size(&args[4]), "image dimensions");
+        let (upper_left, lower_right) = parsed_view(&[&args[5], &args[6]], bounds);
+
+        let mut gray = vec![0; bounds.0 * bounds.1];
+        let rows_per_band = bounds.1 / num_cpus::get() + 1;
+        render_bands(
+            &mut gray,
+            bounds,
+            upper_left,
+            lower_right,
+            rows_per_band,
+            &BandHooks::default(),
+            |band, band_bounds, band_upper_left, band_lower_right| {
+                render(band, band_bounds, band_upper_left, band_lower_right, limit)
+            },
+        );
+
+        let mut overlay = Overlay {
+            pixels: gray.iter().flat_map(|&g| [g, g, g]).collect(),
+            bounds,
+            upper_left,
+            lower_right,
+        };
+        for pair in points.windows(2) {
+            overlay.line(pair[0], pair[1], LINE_COLOR);
+        }
+        for &z in &points {
+            overlay.mark(z, POINT_COLOR);
+        }
+        overlay.mark(c, START_COLOR);
+        io_or_exit(
+            write_rgb_image(&args[3], &overlay.pixels, bounds),
+            "writing PNG file",
+        );
+    }
+}
+
+#[test]
+fn test_orbit_and_period() {
+    // 0 -> -1 -> 0 -> -1 -> ...
+    let c = Complex::new(-1.0, 0.0);
+    assert_eq!(
+        orbit(c, 4),
+        vec![Complex::new(0.0, 0.0), c, Complex::new(0.0, 0.0), c]
+    );
+    assert_eq!(period(c, 255), Some(2));
+    assert_eq!(period(Complex::new(-0.1, 0.1), 255), Some(1));
+
+    // 0 -> 1 -> 2 -> 5, which is outside the escape radius.
+    let orbit = orbit(Complex::new(1.0, 0.0), 255);
+    assert_eq!(orbit.len(), 4);
+    assert_eq!(orbit[3], Complex::new(5.0, 0.0));
+    assert_eq!(period(Complex::new(1.0, 0.0), 255), None);
+}
+
+#[test]
+fn test_distance_estimate() {
+    // The set meets the positive real axis at 1/4.
+    for x in [0.3, 0.5, 1.0, 2.0] {
+        let estimate = distance_estimate(Complex::new(x, 0.0), 1000).unwrap();
+        let distance = x - 0.25;
+        assert!(
+            estimate >= distance / 4.0 && estimate <= 4.0 * distance,
+            "{} {}",
+            x,
+            estimate
+        );
+    }
+    assert_eq!(distance_estimate(Complex::new(-1.0, 0.0), 1000), None);
+}
+
+#[test]
+fn test_in_main_cardioid() {
+    assert!(in_main_cardioid(Complex::new(0.0, 0.0)));
+    assert!(in_main_cardioid(Complex::new(0.24, 0.0)));
+    assert!(in_main_cardioid(Complex::new(-0.74, 0.0)));
+    assert!(!in_main_cardioid(Complex::new(-0.76, 0.0)));
+    assert!(!in_main_cardioid(Complex::new(-1.0, 0.0)));
+    assert!(!in_main_cardioid(Complex::new(0.26, 0.0)));
+}
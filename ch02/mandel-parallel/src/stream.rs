@@ -0,0 +1,164 @@
+//! Encoding the PNG file while the image is still being rendered.
+//!
+//! Bands finish in whatever order their threads do, but a PNG has to be
+//! written from the top down, so the encoder thread holds on to bands that
+//! arrive early until the rows above them have been written.
+
+use crate::palette::Palette;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A PNG file being written, band by band, on its own thread.
+pub struct PngStream {
+    sender: Sender<(usize, Vec<u8>)>,
+    encoder: JoinHandle<io::Result<Timing>>,
+}
+
+/// How long the encoder thread spent.
+#[derive(Clone, Copy, Debug)]
+pub struct Timing {
+    /// Time spent compressing and writing, not counting waiting for bands.
+    pub encoding: Duration,
+    /// When the last row was written.
+    pub finished: Instant,
+}
+
+impl PngStream {
+    /// Create `filename` and start an encoder thread writing an image of
+    /// size `bounds` to it, in grayscale or, as `write_image` does, indexed
+    /// into `palette`.
+    pub fn start(
+        filename: &str,
+        bounds: (usize, usize),
+        palette: Option<&Palette>,
+    ) -> io::Result<PngStream> {
+        let output = BufWriter::new(File::create(filename)?);
+        let plte = palette.map(Palette::plte);
+        let (sender, receiver) = channel::<(usize, Vec<u8>)>();
+
+        let encoder = thread::spawn(move || {
+            let mut encoding = Duration::ZERO;
+            let start = Instant::now();
+            let mut encoder = png::Encoder::new(output, bounds.0 as u32, bounds.1 as u32);
+            encoder.set_depth(png::BitDepth::Eight);
+            match plte {
+                None => encoder.set_color(png::ColorType::Grayscale),
+                Some(plte) => {
+                    encoder.set_color(png::ColorType::Indexed);
+                    encoder.set_palette(plte);
+                }
+            }
+            let mut stream = encoder.write_header()?.into_stream_writer()?;
+            encoding += start.elapsed();
+
+            let mut waiting = BTreeMap::new();
+            let mut next_row = 0;
+            for (top, band) in receiver {
+                waiting.insert(top, band);
+                while let Some(band) = waiting.remove(&next_row) {
+                    let start = Instant::now();
+                    stream.write_all(&band)?;
+                    encoding += start.elapsed();
+                    next_row += band.len() / bounds.0.max(1);
+                }
+            }
+            if next_row < bounds.1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("only {} of {} rows were rendered", next_row, bounds.1),
+                ));
+            }
+
+            let start = Instant::now();
+            stream.finish()?;
+            encoding += start.elapsed();
+            Ok(Timing {
+                encoding,
+                finished: Instant::now(),
+            })
+        });
+        Ok(PngStream { sender, encoder })
+    }
+
+    /// Hand the encoder the band of pixels starting at row `top`.
+    pub fn send(&self, top: usize, band: &[u8]) {
+        // If the encoder has stopped, `finish` reports why.
+        let _ = self.sender.send((top, band.to_vec()));
+    }
+
+    /// Wait for the encoder to write the last of the image.
+    pub fn finish(self) -> io::Result<Timing> {
+        drop(self.sender);
+        match self.encoder.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("the encoder thread panicked")),
+        }
+    }
+}
+
+#[test]
+fn test_stream_matches_write_image() {
+    use num::Complex;
+
+    let bounds = (90, 70);
+    let upper_left = Complex::new(-1.20, 0.35);
+    let lower_right = Complex::new(-1.0, 0.20);
+    let dir = std::env::temp_dir();
+    let streamed = dir.join(format!("mandel-stream-{}.png", std::process::id()));
+    let written = dir.join(format!("mandel-written-{}.png", std::process::id()));
+
+    for palette in [None, Some(Palette::fire())] {
+        let palette = palette.as_ref();
+        let stream = PngStream::start(&streamed.to_string_lossy(), bounds, palette).unwrap();
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        let finished = |top: usize, band: &[u8]| stream.send(top, band);
+        crate::render_bands(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            9,
+            &crate::BandHooks {
+                finished: Some(&finished),
+                ..crate::BandHooks::default()
+            },
+            |band, band_bounds, band_upper_left, band_lower_right| {
+                crate::render(band, band_bounds, band_upper_left, band_lower_right, 255)
+            },
+        );
+        stream.finish().unwrap();
+        crate::write_image(&written.to_string_lossy(), &pixels, bounds, palette).unwrap();
+
+        // The files may be compressed differently; the images must match,
+        // and an indexed one's pixels are its gray levels.
+        let decode = |path: &std::path::Path| {
+            let decoder = png::Decoder::new(File::open(path).unwrap());
+            let mut reader = decoder.read_info().unwrap();
+            let plte = reader.info().palette.as_ref().map(|p| p.to_vec());
+            assert_eq!(plte, palette.map(Palette::plte));
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buffer).unwrap();
+            assert_eq!((info.width, info.height), (90, 70));
+            buffer.truncate(info.buffer_size());
+            buffer
+        };
+        assert_eq!(decode(&streamed), pixels);
+        assert_eq!(decode(&written), pixels);
+    }
+
+    std::fs::remove_file(&streamed).unwrap();
+    std::fs::remove_file(&written).unwrap();
+}
+
+#[test]
+fn test_stream_reports_missing_rows() {
+    let path = std::env::temp_dir().join(format!("mandel-short-{}.png", std::process::id()));
+    let stream = PngStream::start(&path.to_string_lossy(), (4, 4), None).unwrap();
+    stream.send(0, &[0; 8]);
+    assert!(stream.finish().is_err());
+    std::fs::remove_file(&path).unwrap();
+}
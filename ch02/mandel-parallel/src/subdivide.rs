@@ -0,0 +1,287 @@
+//! Mariani-Silver rendering: compute escape times only along the borders of
+//! rectangles, and fill any rectangle whose border is all one value without
+//! iterating its interior.
+//!
+//! This relies on the Mandelbrot set being connected: a region whose whole
+//! border is a single gray level almost never hides anything inside, though
+//! it is a heuristic rather than a guarantee at pixel resolution. Large
+//! areas of the set itself and of the smooth bands around it are filled
+//! directly, which pays off most in deep, high-iteration views.
+//!
+//! Subdivision only pays off on large rectangles, so it is given whole bands
+//! even when a `Control` is watching the render, rather than the thin slices
+//! other strategies get. It counts off rows and checks for cancellation
+//! itself as it goes.
+
+use crate::control::Control;
+use crate::{escape_time, gray_level, pixel_to_point};
+use num::{Complex, Float};
+
+/// Rectangles with a side shorter than this are computed pixel by pixel.
+/// Small rectangles are where a lone escaping pixel in a thin channel can
+/// hide behind a uniform border, so going much lower trades accuracy for
+/// little extra speed.
+const MIN_SIDE: usize = 24;
+
+/// A pixel buffer being filled in, with a note of which pixels are done.
+struct Canvas<'a, T> {
+    pixels: &'a mut [u8],
+    known: Vec<bool>,
+    bounds: (usize, usize),
+    upper_left: Complex<T>,
+    lower_right: Complex<T>,
+    limit: usize,
+    control: Option<&'a Control>,
+    /// How many pixels are known, and how many rows' worth of them have been
+    /// counted off to `control`.
+    pixels_done: usize,
+    rows_counted: usize,
+}
+
+impl<T: Float> Canvas<'_, T> {
+    /// The gray level of the pixel at (`col`, `row`), computing it the same
+    /// way `render` does unless it is already known.
+    fn value(&mut self, col: usize, row: usize) -> u8 {
+        let index = row * self.bounds.0 + col;
+        if !self.known[index] {
+            let point = pixel_to_point(self.bounds, (col, row), self.upper_left, self.lower_right);
+            self.pixels[index] = gray_level(escape_time(point, self.limit), self.limit);
+            self.known[index] = true;
+            self.pixels_done += 1;
+        }
+        self.pixels[index]
+    }
+
+    /// Count off to `control` as many rows as the pixels known so far would
+    /// fill, and return whether to carry on.
+    fn report(&mut self) -> bool {
+        let Some(control) = self.control else {
+            return true;
+        };
+        let rows = self.pixels_done / self.bounds.0;
+        control.add_rows(rows - self.rows_counted);
+        self.rows_counted = rows;
+        !control.is_cancelled()
+    }
+
+    /// Fill the rectangle with corners (`left`, `top`) and (`right`,
+    /// `bottom`), both inclusive. Leaves it unfinished if the render is
+    /// cancelled.
+    fn subdivide(&mut self, left: usize, top: usize, right: usize, bottom: usize) {
+        if !self.report() {
+            return;
+        }
+        let first = self.value(left, top);
+        let mut uniform = true;
+        for col in left..=right {
+            uniform &= self.value(col, top) == first;
+            uniform &= self.value(col, bottom) == first;
+        }
+        for row in top..=bottom {
+            uniform &= self.value(left, row) == first;
+            uniform &= self.value(right, row) == first;
+        }
+
+        if uniform {
+            // A rectangle one or two pixels across has no interior.
+            if right - left < 2 || bottom - top < 2 {
+                return;
+            }
+            for row in top + 1..bottom {
+                let start = row * self.bounds.0;
+                self.pixels[start + left + 1..start + right].fill(first);
+                self.known[start + left + 1..start + right].fill(true);
+            }
+            self.pixels_done += (right - left - 1) * (bottom - top - 1);
+        } else if right - left < MIN_SIDE || bottom - top < MIN_SIDE {
+            for row in top + 1..bottom {
+                for col in left + 1..right {
+                    self.value(col, row);
+                }
+            }
+        } else if right - left >= bottom - top {
+            let middle = (left + right) / 2;
+            self.subdivide(left, top, middle, bottom);
+            self.subdivide(middle, top, right, bottom);
+        } else {
+            let middle = (top + bottom) / 2;
+            self.subdivide(left, top, right, middle);
+            self.subdivide(left, middle, right, bottom);
+        }
+    }
+}
+
+/// Render a rectangle of the Mandelbrot set into a buffer of pixels by
+/// Mariani-Silver subdivision.
+///
+/// The arguments and the resulting pixels are the same as for `render`.
+/// With `control`, rows are counted off as they are finished, and the
+/// pixels are left unfinished if the render is cancelled.
+pub fn render_subdivided<T: Float>(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<T>,
+    lower_right: Complex<T>,
+    limit: usize,
+    control: Option<&Control>,
+) {
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    if pixels.is_empty() {
+        return;
+    }
+
+    let mut canvas = Canvas {
+        known: vec![false; pixels.len()],
+        pixels,
+        bounds,
+        upper_left,
+        lower_right,
+        limit,
+        control,
+        pixels_done: 0,
+        rows_counted: 0,
+    };
+    canvas.subdivide(0, 0, bounds.0 - 1, bounds.1 - 1);
+    canvas.report();
+}
+
+#[test]
+fn test_subdivided_matches_brute_force() {
+    // The example view from the usage message, in the same bands the
+    // threaded renderer would use.
+    let bounds = (1000, 750);
+    let upper_left = Complex {
+        re: -1.20,
+        im: 0.35,
+    };
+    let lower_right = Complex { re: -1.0, im: 0.20 };
+
+    let mut expected = vec![0; bounds.0 * bounds.1];
+    let mut subdivided = vec![0; bounds.0 * bounds.1];
+    let rows_per_band = bounds.1 / 8 + 1;
+    crate::render_bands(
+        &mut expected,
+        bounds,
+        upper_left,
+        lower_right,
+        rows_per_band,
+        &crate::BandHooks::default(),
+        |band, band_bounds, band_upper_left, band_lower_right| {
+            crate::render(band, band_bounds, band_upper_left, band_lower_right, 255)
+        },
+    );
+    crate::render_bands(
+        &mut subdivided,
+        bounds,
+        upper_left,
+        lower_right,
+        rows_per_band,
+        &crate::BandHooks::default(),
+        |band, band_bounds, band_upper_left, band_lower_right| {
+            render_subdivided(
+                band,
+                band_bounds,
+                band_upper_left,
+                band_lower_right,
+                255,
+                None,
+            )
+        },
+    );
+    let differ = expected
+        .iter()
+        .zip(&subdivided)
+        .filter(|(a, b)| a != b)
+        .count();
+    assert_eq!(differ, 0);
+}
+
+#[test]
+fn test_subdivided_small_images() {
+    let upper_left = Complex { re: -2.0, im: 1.0 };
+    let lower_right = Complex { re: 1.0, im: -1.0 };
+    for bounds in [(1, 1), (1, 9), (9, 1), (3, 2), (17, 13)] {
+        let mut expected = vec![0; bounds.0 * bounds.1];
+        let mut subdivided = vec![0; bounds.0 * bounds.1];
+        crate::render(&mut expected, bounds, upper_left, lower_right, 255);
+        render_subdivided(&mut subdivided, bounds, upper_left, lower_right, 255, None);
+        assert_eq!(expected, subdivided, "{:?}", bounds);
+    }
+}
+
+#[test]
+fn test_subdivided_under_control() {
+    // As the binary renders it, with progress and cancellation.
+    let bounds = (1000, 750);
+    let upper_left = Complex {
+        re: -1.20,
+        im: 0.35,
+    };
+    let lower_right = Complex { re: -1.0, im: 0.20 };
+    let render_with = |control: &Control, strategy: crate::Strategy| {
+        let mut pixels = vec![0; bounds.0 * bounds.1];
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        crate::render_bands(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            bounds.1 / 2 + 1,
+            &crate::BandHooks {
+                control: Some(control),
+                whole_bands: strategy.renders_whole_bands(),
+                ..crate::BandHooks::default()
+            },
+            |band, band_bounds, band_upper_left, band_lower_right| {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                strategy.render(
+                    band,
+                    band_bounds,
+                    band_upper_left,
+                    band_lower_right,
+                    255,
+                    Some(control),
+                )
+            },
+        );
+        (pixels, calls.into_inner())
+    };
+
+    let control = Control::new(bounds.1);
+    let (expected, calls) = render_with(&control, crate::Strategy::Pixels);
+    assert!(calls > 2);
+    let control = Control::new(bounds.1);
+    let (subdivided, calls) = render_with(&control, crate::Strategy::Subdivide);
+    assert_eq!(expected, subdivided);
+    assert_eq!(control.rows_done(), bounds.1);
+    // One call per band: slices would be too thin to subdivide.
+    assert_eq!(calls, 2);
+
+    // Cancelled before it starts, nothing is finished.
+    let control = Control::new(bounds.1);
+    control.cancel();
+    let (pixels, _) = render_with(&control, crate::Strategy::Subdivide);
+    assert!(pixels.iter().all(|&p| p == 0));
+    assert_eq!(control.rows_done(), 0);
+}
+
+#[test]
+fn test_subdivided_uniform_thin_bands() {
+    // Wholly inside the set, so the border is uniform, but one pixel thick.
+    let upper_left = Complex::new(-0.1, 0.1);
+    let lower_right = Complex::new(0.1, -0.1);
+    for bounds in [(30, 1), (1, 30)] {
+        let control = Control::new(bounds.1);
+        let mut pixels = vec![1; bounds.0 * bounds.1];
+        render_subdivided(
+            &mut pixels,
+            bounds,
+            upper_left,
+            lower_right,
+            255,
+            Some(&control),
+        );
+        assert!(pixels.iter().all(|&p| p == 0), "{:?}", bounds);
+        assert_eq!(control.rows_done(), bounds.1, "{:?}", bounds);
+    }
+}
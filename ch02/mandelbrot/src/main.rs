@@ -1,44 +1,188 @@
 use num::Complex;
-use std::str::FromStr;
 use image::ColorType;
 use image::png::PNGEncoder;
+use std::fmt;
 use std::fs::File;
 use std::env;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::thread;
+
+// Shared word for word with mandel-parallel, which uses some parts this
+// program doesn't.
+#[allow(dead_code)]
+mod palette;
+#[allow(dead_code)]
+mod parse;
+
+use palette::Palette;
+use parse::{parse_corners, parse_size};
+
+/// The iteration limit, unless `--iterations` says otherwise.
+const DEFAULT_LIMIT: usize = 255;
+
+/// Exit statuses, after the BSD `sysexits.h` conventions: the command line
+/// was malformed, an argument didn't parse or made no sense, or the image
+/// couldn't be written.
+const EXIT_USAGE: i32 = 64;
+const EXIT_PARSE: i32 = 65;
+const EXIT_IO: i32 = 74;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let program = &args[0];
 
-    if args.len() != 5 {
-        eprintln!("Usage: {} FILE PIXELS UPPERLEFT LOWERRIGHT",
-                  args[0]);
-        eprintln!("Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
-                  args[0]);
-        std::process::exit(1);
+    match args.get(1).map(String::as_str) {
+        Some("help") => print_usage(program, &mut io::stdout()),
+        Some("render") => render_main(program, &args[2..]),
+        _ => render_main(program, &args[1..]),
     }
+}
 
-    let bounds = parse_pair(&args[2], 'x')
-        .expect("error parsing image dimensions");
-    let upper_left = parse_complex(&args[3])
-        .expect("error parsing upper left corner point");
-    let lower_right = parse_complex(&args[4])
-        .expect("error parsing lower right corner point");
+/// What was wrong with the command line: its shape, or one of the values
+/// in it.
+enum ArgError {
+    Usage(String),
+    Invalid(String),
+}
 
-    let mut pixels = vec![0; bounds.0 * bounds.1];
+/// The options `render_main` understands, and the positional arguments
+/// left over.
+struct RenderArgs<'a> {
+    output: Option<&'a str>,
+    threads: usize,
+    limit: usize,
+    palette: Option<Palette>,
+    positional: Vec<&'a str>,
+}
 
-    render(&mut pixels, bounds, upper_left, lower_right);
+impl<'a> RenderArgs<'a> {
+    /// Sort `args` into options and positional arguments, or say what was
+    /// wrong with them. Returns `None` if help was asked for.
+    fn parse(args: &'a [String]) -> Result<Option<RenderArgs<'a>>, ArgError> {
+        let mut parsed = RenderArgs {
+            output: None,
+            threads: 1,
+            limit: DEFAULT_LIMIT,
+            palette: None,
+            positional: Vec::new(),
+        };
+        let mut args = args.iter().map(String::as_str);
+        while let Some(arg) = args.next() {
+            match arg {
+                "-h" | "--help" => return Ok(None),
+                "--output" | "--threads" | "--iterations" | "--palette" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| ArgError::Usage(format!("{} needs a value", arg)))?;
+                    match arg {
+                        "--output" => parsed.output = Some(value),
+                        "--threads" => parsed.threads = count(arg, value)?,
+                        "--palette" => {
+                            parsed.palette = Some(Palette::from_str(value).map_err(|e| {
+                                ArgError::Invalid(format!("error parsing {}: {}", arg, e))
+                            })?);
+                        }
+                        _ => parsed.limit = count(arg, value)?,
+                    }
+                }
+                _ if arg.starts_with("--") => {
+                    return Err(ArgError::Usage(format!("unknown option '{}'", arg)));
+                }
+                _ => parsed.positional.push(arg),
+            }
+        }
+        Ok(Some(parsed))
+    }
+}
 
-    write_image(&args[1], &pixels, bounds)
-        .expect("error writing PNG file");
+/// Parse the `value` of `option`, which must be at least 1.
+fn count(option: &str, value: &str) -> Result<usize, ArgError> {
+    match usize::from_str(value) {
+        Ok(0) => Err(ArgError::Invalid(format!("{} must be at least 1", option))),
+        Ok(n) => Ok(n),
+        Err(e) => Err(ArgError::Invalid(format!("error parsing {} '{}': {}", option, value, e))),
+    }
 }
 
-/// An infinite loop that computes `z = z * z + c`.
-fn complex_square_add_loop(c: Complex<f64>) {
-    let mut z = Complex { re: 0.0, im: 0.0 };
-    loop {
-        z = z * z + c;
+/// Render the image described by `args`, the command line after the
+/// program name and any subcommand.
+fn render_main(program: &str, args: &[String]) {
+    let mut args = match RenderArgs::parse(args) {
+        Ok(Some(args)) => args,
+        Ok(None) => return print_usage(program, &mut io::stdout()),
+        Err(ArgError::Invalid(message)) => invalid(&message),
+        Err(ArgError::Usage(message)) => {
+            eprintln!("{}", message);
+            usage_error(program);
+        }
+    };
+    let output = match args.output {
+        Some(output) => output,
+        None if !args.positional.is_empty() => args.positional.remove(0),
+        None => usage_error(program),
+    };
+    if args.positional.len() != 2 && args.positional.len() != 3 {
+        usage_error(program);
+    }
+
+    let bounds = parsed(parse_size(args.positional[0]), "image dimensions");
+    let (upper_left, lower_right): (Complex<f64>, Complex<f64>) =
+        parsed(parse_corners(&args.positional[1..], bounds), "view");
+    if !(upper_left.re < lower_right.re && upper_left.im > lower_right.im) {
+        invalid("the upper left corner must be above and to the left of \
+                 the lower right corner");
+    }
+
+    let mut pixels = vec![0; bounds.0 * bounds.1];
+
+    render_in_bands(&mut pixels, bounds, upper_left, lower_right,
+                    args.limit, args.threads);
+
+    if let Err(e) = write_image(output, &pixels, bounds, args.palette.as_ref()) {
+        eprintln!("error writing PNG file {}: {}", output, e);
+        std::process::exit(EXIT_IO);
     }
 }
 
+fn print_usage(program: &str, out: &mut dyn Write) {
+    let _ = writeln!(out, "\
+Usage: {program} [OPTIONS] FILE PIXELS UPPERLEFT LOWERRIGHT
+       {program} [OPTIONS] FILE PIXELS CENTER@ZOOM
+       {program} help
+Options:
+  --output FILE     write the image to FILE, leaving FILE out
+  --threads N       render in N bands at once (default 1)
+  --iterations N    give up on points after N iterations (default {DEFAULT_LIMIT})
+  --palette NAME    color the image, writing an indexed PNG; NAME is one of
+                    {names}
+  --help            show this message
+PIXELS is WIDTHxHEIGHT or a name like 1080p or 4k; points are re,im or a+bi.
+Example: {program} mandel.png 1000x750 -1.20,0.35 -1,0.20
+         {program} --threads 8 mandel.png 1080p -0.7436447+0.1318252i@1e4",
+        names = palette::NAMES);
+}
+
+/// Print the usage message and exit, for a malformed command line.
+fn usage_error(program: &str) -> ! {
+    print_usage(program, &mut io::stderr());
+    std::process::exit(EXIT_USAGE);
+}
+
+/// Report an argument that didn't parse, or makes no sense, and exit.
+fn invalid(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_PARSE);
+}
+
+/// Unwrap the parsed argument described by `what`, or explain what was
+/// wrong with it and exit.
+fn parsed<T, E: fmt::Display>(result: Result<T, E>, what: &str) -> T {
+    result.unwrap_or_else(|error| {
+        invalid(&format!("error parsing {}: {}", what, error))
+    })
+}
+
 /// Determines whether `c` escapes to infinity within `limit` iterations.
 fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
     let mut z = Complex { re: 0.0, im: 0.0 };
@@ -53,22 +197,16 @@ fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
 }
 
 /// Parses a pair of values separated by `separator` from the string `s`.
+/// Only the tests still want an `Option`; `main` reports why it failed.
+#[cfg(test)]
 fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
-    match s.find(separator) {
-        None => None,
-        Some(index) => match (T::from_str(&s[..index]), T::from_str(&s[index + 1..])) {
-            (Ok(l), Ok(r)) => Some((l, r)),
-            _ => None,
-        },
-    }
+    parse::parse_pair(s, separator).ok()
 }
 
-/// Parses a complex number from a string of the form "real,imaginary".
+/// Parses a complex number written "real,imaginary" or "a+bi".
+#[cfg(test)]
 fn parse_complex(s: &str) -> Option<Complex<f64>> {
-    match parse_pair::<f64>(s, ',') {
-        Some((re, im)) => Some(Complex { re, im }),
-        None => None,
-    }
+    parse::parse_point(s).ok()
 }
 
 /// Given the row and column of a pixel in the output image, return the
@@ -105,26 +243,61 @@ fn render(
     bounds: (usize, usize),
     upper_left: Complex<f64>,
     lower_right: Complex<f64>,
+    limit: usize,
 ) {
     assert!(pixels.len() == bounds.0 * bounds.1);
 
     for row in 0..bounds.1 {
         for col in 0..bounds.0 {
             let point = pixel_to_point(bounds, (col, row), upper_left, lower_right);
-            pixels[row * bounds.0 + col] = match escape_time(point, 255) {
+            pixels[row * bounds.0 + col] = match escape_time(point, limit) {
                 None => 0,
-                Some(count) => 255 - count as u8,
+                Some(count) => 255 - (count * 255 / limit) as u8,
             };
         }
     }
 }
 
+/// Render the image into `pixels` as `threads` horizontal bands, each on
+/// its own thread.
+fn render_in_bands(
+    pixels: &mut [u8],
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    limit: usize,
+    threads: usize,
+) {
+    let rows_per_band = bounds.1 / threads + 1;
+    let bands = pixels.chunks_mut(rows_per_band * bounds.0);
+    thread::scope(|spawner| {
+        for (i, band) in bands.enumerate() {
+            let top = rows_per_band * i;
+            let height = band.len() / bounds.0;
+            let band_bounds = (bounds.0, height);
+            let band_upper_left =
+                pixel_to_point(bounds, (0, top), upper_left, lower_right);
+            let band_lower_right =
+                pixel_to_point(bounds, (bounds.0, top + height),
+                               upper_left, lower_right);
+            spawner.spawn(move || {
+                render(band, band_bounds, band_upper_left, band_lower_right,
+                       limit);
+            });
+        }
+    });
+}
+
 /// Write the buffer `pixels`, whose dimensions are given by `bounds`, to the
 /// file named `filename`.
+///
+/// With a `palette`, the file is an indexed-color PNG, with each pixel's gray
+/// level as its index into the palette.
 fn write_image(
     filename: &str,
     pixels: &[u8],
     bounds: (usize, usize),
+    palette: Option<&Palette>,
 ) -> Result<(), std::io::Error> {
     // equivalent to:
     // let output = match File::create(filename) {
@@ -134,13 +307,26 @@ fn write_image(
     //     }
     // };
     let output = File::create(filename)?;
-    let encoder = PNGEncoder::new(output);
-    encoder.encode(
-        &pixels,
-        bounds.0 as u32,
-        bounds.1 as u32,
-        ColorType::Gray(8),
-    )?;
+    match palette {
+        None => {
+            let encoder = PNGEncoder::new(output);
+            encoder.encode(
+                pixels,
+                bounds.0 as u32,
+                bounds.1 as u32,
+                ColorType::Gray(8),
+            )?;
+        }
+        Some(palette) => {
+            let mut encoder = png::Encoder::new(BufWriter::new(output),
+                                                bounds.0 as u32,
+                                                bounds.1 as u32);
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(palette.plte());
+            encoder.write_header()?.write_image_data(pixels)?;
+        }
+    }
     Ok(())
 }
 
@@ -0,0 +1,111 @@
+//! Built-in palettes for coloring the gray levels `render` produces.
+//!
+//! A palette has one color for each of the 256 levels, so an image colored
+//! with one can be written as an indexed PNG: each pixel is stored as its
+//! level, and the colors once, in the file's PLTE chunk. That is a third the
+//! size of the same image in RGB, before compression, and it can be
+//! recolored by replacing that one chunk.
+//!
+//! The same file is in both `mandelbrot` and `mandel-parallel`: a change to
+//! one belongs in the other.
+
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+
+/// The names `--palette` knows, as the usage message lists them.
+pub const NAMES: &str = "gray|fire|ocean|rainbow";
+
+/// A color for each gray level. Level 0, the set itself, is always black.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    name: &'static str,
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    /// Build a palette from `color`, which maps a level scaled to between 0
+    /// and 1 to red, green and blue, each between 0 and 1.
+    fn from_fn(name: &'static str, color: impl Fn(f64) -> [f64; 3]) -> Palette {
+        let mut colors = vec![[0, 0, 0]];
+        colors.extend((1..=255).map(|level| {
+            color(level as f64 / 255.0).map(|c| (255.0 * c.clamp(0.0, 1.0)).round() as u8)
+        }));
+        Palette { name, colors }
+    }
+
+    /// The levels as they are: black in the set, brighter the sooner a point
+    /// escapes.
+    pub fn gray() -> Palette {
+        Palette::from_fn("gray", |t| [t, t, t])
+    }
+
+    /// From dark red through orange and yellow to white.
+    pub fn fire() -> Palette {
+        Palette::from_fn("fire", |t| [3.0 * t, 3.0 * t - 1.0, 3.0 * t - 2.0])
+    }
+
+    /// From deep blue through teal to pale cyan.
+    pub fn ocean() -> Palette {
+        Palette::from_fn("ocean", |t| {
+            [0.5 * t * t * t, 0.1 + 0.85 * t * t, 0.3 + 0.7 * t]
+        })
+    }
+
+    /// A rainbow whose ends meet, so that it can be rotated without a seam.
+    pub fn rainbow() -> Palette {
+        Palette::from_fn("rainbow", |t| {
+            [0.0, 1.0 / 3.0, 2.0 / 3.0].map(|phase| 0.5 + 0.5 * (TAU * (t + phase)).cos())
+        })
+    }
+
+    /// The color of `level`.
+    pub fn color(&self, level: u8) -> [u8; 3] {
+        self.colors[level as usize]
+    }
+
+    /// The palette as the contents of a PLTE chunk: red, green and blue for
+    /// each level in turn.
+    pub fn plte(&self) -> Vec<u8> {
+        self.colors.concat()
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        match s {
+            "gray" | "grey" => Ok(Palette::gray()),
+            "fire" => Ok(Palette::fire()),
+            "ocean" => Ok(Palette::ocean()),
+            "rainbow" => Ok(Palette::rainbow()),
+            _ => Err(format!("unknown palette '{}', expected {}", s, NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+#[test]
+fn test_palettes() {
+    for name in NAMES.split('|') {
+        let palette: Palette = name.parse().unwrap();
+        assert_eq!(palette.to_string(), name);
+        assert_eq!(palette.plte().len(), 3 * 256);
+        assert_eq!(palette.color(0), [0, 0, 0]);
+    }
+    assert!("plaid".parse::<Palette>().is_err());
+
+    assert_eq!(Palette::gray().color(200), [200, 200, 200]);
+    assert_eq!(Palette::gray().plte()[600..603], [200, 200, 200]);
+    assert_eq!(Palette::fire().color(255), [255, 255, 255]);
+    // The rainbow comes back round to where it started.
+    let rainbow = Palette::rainbow();
+    let [r, g, b] = rainbow.color(255);
+    assert!(r == 255 && g <= 64 && b <= 64, "{:?}", rainbow.color(255));
+}
@@ -0,0 +1,388 @@
+//! Parsing image sizes, points on the complex plane and views from the
+//! command line, with errors that say what was wrong and where.
+//!
+//! Points can be written `re,im` or `a+bi`, sizes `WIDTHxHEIGHT` or by name,
+//! like `1080p` or `4k`, and a view either by its corners or as
+//! `CENTER@ZOOM`. Numbers can have exponents and spaces around them.
+//!
+//! The same file is in both `mandelbrot` and `mandel-parallel`: a change to
+//! one belongs in the other.
+
+use num::{Complex, Float};
+use std::fmt;
+use std::str::FromStr;
+
+/// How wide a view at zoom 1 is: enough for the whole set.
+const ZOOM_ONE_WIDTH: f64 = 4.0;
+
+/// Named image sizes, as width and height.
+const SIZES: [(&str, (usize, usize)); 8] = [
+    ("vga", (640, 480)),
+    ("720p", (1280, 720)),
+    ("hd", (1280, 720)),
+    ("1080p", (1920, 1080)),
+    ("fullhd", (1920, 1080)),
+    ("1440p", (2560, 1440)),
+    ("4k", (3840, 2160)),
+    ("8k", (7680, 4320)),
+];
+
+/// The most pixels an image may have: 16384x16384, or a quarter of a
+/// gigabyte of gray levels. Anything much larger is more likely a typo than
+/// a render that would fit in memory.
+pub const MAX_PIXELS: usize = 1 << 28;
+
+/// What was wrong with an argument.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    Empty,
+    BadNumber,
+    NotFinite,
+    NotPositive,
+    MissingSeparator(char),
+    MissingImaginaryPart,
+    UnknownSize,
+    TooLarge,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::Empty => write!(f, "nothing to parse"),
+            ParseErrorKind::BadNumber => write!(f, "expected a number"),
+            ParseErrorKind::NotFinite => write!(f, "expected a finite number"),
+            ParseErrorKind::NotPositive => write!(f, "expected a number greater than zero"),
+            ParseErrorKind::MissingSeparator(c) => {
+                write!(f, "expected '{}'", c)
+            }
+            ParseErrorKind::MissingImaginaryPart => write!(
+                f,
+                "expected an imaginary part, as in '-0.5,0.25' or '-0.5+0.25i'"
+            ),
+            ParseErrorKind::UnknownSize => write!(
+                f,
+                "expected WIDTHxHEIGHT or one of {}",
+                SIZES.map(|(name, _)| name).join(", ")
+            ),
+            ParseErrorKind::TooLarge => {
+                write!(f, "expected an image of at most {} pixels", MAX_PIXELS)
+            }
+        }
+    }
+}
+
+/// An argument that couldn't be parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub input: String,
+    /// The byte offset in `input` where the problem was found.
+    pub position: usize,
+}
+
+impl ParseError {
+    /// The column of the problem, counting characters from 1.
+    pub fn column(&self) -> usize {
+        self.input[..self.position].chars().count() + 1
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at column {} of '{}'",
+            self.kind,
+            self.column(),
+            self.input
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A failure partway through parsing: what went wrong, and at which byte
+/// offset of the whole argument.
+type Partial<T> = Result<T, (ParseErrorKind, usize)>;
+
+fn finish<T>(input: &str, result: Partial<T>) -> Result<T, ParseError> {
+    result.map_err(|(kind, position)| ParseError {
+        kind,
+        input: input.to_string(),
+        position,
+    })
+}
+
+/// Parse the number in `s`, which starts `offset` bytes into the argument,
+/// ignoring spaces around it.
+fn number<T: FromStr>(s: &str, offset: usize) -> Partial<T> {
+    let start = offset + s.len() - s.trim_start().len();
+    T::from_str(s.trim()).map_err(|_| (ParseErrorKind::BadNumber, start))
+}
+
+fn finite<T: Float + FromStr>(s: &str, offset: usize) -> Partial<T> {
+    let value: T = number(s, offset)?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err((
+            ParseErrorKind::NotFinite,
+            offset + s.len() - s.trim_start().len(),
+        ))
+    }
+}
+
+fn pair<T: FromStr>(s: &str, offset: usize, separator: char) -> Partial<(T, T)> {
+    match s.find(separator) {
+        None => Err((
+            ParseErrorKind::MissingSeparator(separator),
+            offset + s.trim_end().len(),
+        )),
+        Some(index) => Ok((
+            number(&s[..index], offset)?,
+            number(&s[index + 1..], offset + index + 1)?,
+        )),
+    }
+}
+
+/// Parses a pair of values separated by `separator` from the string `s`.
+pub fn parse_pair<T: FromStr>(s: &str, separator: char) -> Result<(T, T), ParseError> {
+    finish(s, pair(s, 0, separator))
+}
+
+fn point<T: Float + FromStr>(s: &str, offset: usize) -> Partial<Complex<T>> {
+    if s.trim().is_empty() {
+        return Err((ParseErrorKind::Empty, offset));
+    }
+    if let Some(index) = s.find(',') {
+        return Ok(Complex {
+            re: finite(&s[..index], offset)?,
+            im: finite(&s[index + 1..], offset + index + 1)?,
+        });
+    }
+
+    let body = s.trim_end();
+    let body = match body.strip_suffix('i') {
+        Some(body) => body,
+        None => return Err((ParseErrorKind::MissingImaginaryPart, offset + body.len())),
+    };
+    // The sign between the parts is the last one that isn't leading or part
+    // of an exponent.
+    let bytes = body.as_bytes();
+    let split = (1..bytes.len()).rev().find(|&i| {
+        let before = body[..i].trim_end();
+        matches!(bytes[i], b'+' | b'-') && !before.is_empty() && !before.ends_with(['e', 'E'])
+    });
+    let (re, sign, imaginary) = match split {
+        Some(i) => (
+            finite(&body[..i], offset)?,
+            &body[i..i + 1],
+            (&body[i + 1..], offset + i + 1),
+        ),
+        None => (T::zero(), "+", (body, offset)),
+    };
+    let magnitude = if imaginary.0.trim().is_empty() {
+        T::one()
+    } else {
+        finite(imaginary.0, imaginary.1)?
+    };
+    Ok(Complex {
+        re,
+        im: if sign == "-" { -magnitude } else { magnitude },
+    })
+}
+
+/// Parses a point on the complex plane, written either `re,im` or `a+bi`.
+pub fn parse_point<T: Float + FromStr>(s: &str) -> Result<Complex<T>, ParseError> {
+    finish(s, point(s, 0))
+}
+
+fn size(s: &str) -> Partial<(usize, usize)> {
+    let name = s.trim().to_ascii_lowercase();
+    if let Some(&(_, size)) = SIZES.iter().find(|(alias, _)| *alias == name) {
+        return Ok(size);
+    }
+    let index = match s.find(['x', 'X']) {
+        Some(index) => index,
+        None if name.is_empty() => return Err((ParseErrorKind::Empty, 0)),
+        None if name.parse::<usize>().is_ok() => {
+            return Err((ParseErrorKind::MissingSeparator('x'), s.trim_end().len()))
+        }
+        None => return Err((ParseErrorKind::UnknownSize, 0)),
+    };
+    let separator = s[index..].chars().next().unwrap();
+    let (width, height): (usize, usize) = pair(s, 0, separator)?;
+    if width == 0 {
+        return Err((ParseErrorKind::NotPositive, 0));
+    }
+    if height == 0 {
+        return Err((ParseErrorKind::NotPositive, index + 1));
+    }
+    if width
+        .checked_mul(height)
+        .is_none_or(|pixels| pixels > MAX_PIXELS)
+    {
+        return Err((ParseErrorKind::TooLarge, 0));
+    }
+    Ok((width, height))
+}
+
+/// Parses an image size: `WIDTHxHEIGHT`, or a name like `1080p` or `4k`.
+pub fn parse_size(s: &str) -> Result<(usize, usize), ParseError> {
+    finish(s, size(s))
+}
+
+/// The upper-left and lower-right corners of a view of size `bounds`
+/// centered on `center` and `width` wide on the complex plane.
+pub fn viewport<T: Float>(
+    center: Complex<T>,
+    width: T,
+    bounds: (usize, usize),
+) -> (Complex<T>, Complex<T>) {
+    let count = |n: usize| T::from(n).unwrap();
+    let half = Complex::new(
+        width / count(2),
+        width * count(bounds.1) / count(bounds.0) / count(2),
+    );
+    (
+        Complex::new(center.re - half.re, center.im + half.im),
+        Complex::new(center.re + half.re, center.im - half.im),
+    )
+}
+
+/// Parses a view of size `bounds` written as `CENTER@ZOOM`, returning its
+/// upper-left and lower-right corners. At zoom 1 the view is
+/// `ZOOM_ONE_WIDTH` wide.
+pub fn parse_view<T: Float + FromStr>(
+    s: &str,
+    bounds: (usize, usize),
+) -> Result<(Complex<T>, Complex<T>), ParseError> {
+    finish(s, {
+        match s.rfind('@') {
+            None => Err((ParseErrorKind::MissingSeparator('@'), s.trim_end().len())),
+            Some(index) => point(&s[..index], 0).and_then(|center| {
+                let zoom: T = finite(&s[index + 1..], index + 1)?;
+                if zoom <= T::zero() {
+                    return Err((ParseErrorKind::NotPositive, index + 1));
+                }
+                let width = T::from(ZOOM_ONE_WIDTH).unwrap() / zoom;
+                Ok(viewport(center, width, bounds))
+            }),
+        }
+    })
+}
+
+/// Parses the corners of a view of size `bounds` from `args`: either an
+/// upper-left and a lower-right point, or a single `CENTER@ZOOM`.
+pub fn parse_corners<T: Float + FromStr>(
+    args: &[&str],
+    bounds: (usize, usize),
+) -> Result<(Complex<T>, Complex<T>), ParseError> {
+    match args {
+        [view] => parse_view(view, bounds),
+        [upper_left, lower_right, ..] => Ok((parse_point(upper_left)?, parse_point(lower_right)?)),
+        [] => Err(ParseError {
+            kind: ParseErrorKind::Empty,
+            input: String::new(),
+            position: 0,
+        }),
+    }
+}
+
+#[test]
+fn test_parse_point() {
+    let point = |s| parse_point::<f64>(s);
+    assert_eq!(point("1.25,-0.0625"), Ok(Complex::new(1.25, -0.0625)));
+    assert_eq!(point(" -1.5 , 2e-3 "), Ok(Complex::new(-1.5, 0.002)));
+    assert_eq!(point("-0.75+0.1i"), Ok(Complex::new(-0.75, 0.1)));
+    assert_eq!(point("-0.75 - 0.1i"), Ok(Complex::new(-0.75, -0.1)));
+    assert_eq!(point("1e-5-2.5E+3i"), Ok(Complex::new(1e-5, -2500.0)));
+    assert_eq!(point("-2i"), Ok(Complex::new(0.0, -2.0)));
+    assert_eq!(point("0.5-i"), Ok(Complex::new(0.5, -1.0)));
+
+    let error = point("-1.2,x").unwrap_err();
+    assert_eq!(error.kind, ParseErrorKind::BadNumber);
+    assert_eq!(error.column(), 6);
+    assert_eq!(
+        error.to_string(),
+        "expected a number at column 6 of '-1.2,x'"
+    );
+    assert_eq!(
+        point("1.0").unwrap_err().kind,
+        ParseErrorKind::MissingImaginaryPart
+    );
+    assert_eq!(point("1,inf").unwrap_err().kind, ParseErrorKind::NotFinite);
+    assert_eq!(point("  ").unwrap_err().kind, ParseErrorKind::Empty);
+    assert_eq!(point("1+2+3i").unwrap_err().position, 0);
+}
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("1000x750"), Ok((1000, 750)));
+    assert_eq!(parse_size(" 640 X 480 "), Ok((640, 480)));
+    assert_eq!(parse_size("4K"), Ok((3840, 2160)));
+    assert_eq!(parse_size("1080p"), Ok((1920, 1080)));
+
+    assert_eq!(
+        parse_size("huge").unwrap_err().kind,
+        ParseErrorKind::UnknownSize
+    );
+    assert_eq!(
+        parse_size("1000").unwrap_err().kind,
+        ParseErrorKind::MissingSeparator('x')
+    );
+    let error = parse_size("100x0").unwrap_err();
+    assert_eq!(
+        (error.kind, error.column()),
+        (ParseErrorKind::NotPositive, 5)
+    );
+    let error = parse_size("100x-5").unwrap_err();
+    assert_eq!((error.kind, error.column()), (ParseErrorKind::BadNumber, 5));
+
+    assert_eq!(parse_size("16384x16384"), Ok((16384, 16384)));
+    for huge in [
+        "16384x16385",
+        "100000000x100000000",
+        "18446744073709551615x2",
+    ] {
+        assert_eq!(
+            parse_size(huge).unwrap_err().kind,
+            ParseErrorKind::TooLarge,
+            "{}",
+            huge
+        );
+    }
+}
+
+#[test]
+fn test_parse_view() {
+    let (upper_left, lower_right) = parse_view::<f64>("-0.5,0@2", (200, 100)).unwrap();
+    assert_eq!(upper_left, Complex::new(-1.5, 0.5));
+    assert_eq!(lower_right, Complex::new(0.5, -0.5));
+    assert_eq!(
+        parse_corners::<f64>(&["-1-0.5i@1e1"], (4, 4)),
+        Ok((Complex::new(-1.2, -0.3), Complex::new(-0.8, -0.7)))
+    );
+    assert_eq!(
+        parse_corners::<f64>(&["-1,1", "1,-1"], (4, 4)),
+        Ok((Complex::new(-1.0, 1.0), Complex::new(1.0, -1.0)))
+    );
+
+    let error = parse_view::<f64>("-0.5,0@0", (200, 100)).unwrap_err();
+    assert_eq!(
+        (error.kind, error.column()),
+        (ParseErrorKind::NotPositive, 8)
+    );
+    assert_eq!(
+        parse_view::<f64>("-0.5,0", (200, 100)).unwrap_err().kind,
+        ParseErrorKind::MissingSeparator('@')
+    );
+}
+
+#[test]
+fn test_viewport() {
+    assert_eq!(
+        viewport(Complex::new(-1.0, 0.5), 4.0, (200, 100)),
+        (Complex::new(-3.0, 1.5), Complex::new(1.0, -0.5))
+    );
+}
@@ -0,0 +1,138 @@
+//! Showing what a replacement would change without making it, as a unified
+//! diff colored the way `git diff` colors one, when it is going to a
+//! terminal.
+
+use similar::{ChangeTag, TextDiff};
+use std::fmt;
+use std::io::{self, Write};
+use text_colorizer::*;
+
+/// The lines of context shown around each change, unless `--context` says
+/// otherwise.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// Write a unified diff from `old`, the text of the file `old_name`, to
+/// `new`, the text of `new_name`, with `context` unchanged lines around each
+/// change, in color if `color` is set. Writes nothing if the texts are the
+/// same.
+pub fn write_diff(
+    out: &mut impl Write,
+    old_name: &str,
+    new_name: &str,
+    old: &str,
+    new: &str,
+    context: usize,
+    color: bool,
+) -> io::Result<()> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut unified = diff.unified_diff();
+    unified.context_radius(context);
+
+    for (i, hunk) in unified.iter_hunks().enumerate() {
+        if i == 0 {
+            write_line(out, &format!("--- {}", old_name), color, |s| s.bold())?;
+            write_line(out, &format!("+++ {}", new_name), color, |s| s.bold())?;
+        }
+        write_line(out, &hunk.header().to_string(), color, |s| s.cyan())?;
+        for change in hunk.iter_changes() {
+            let line = change.value().trim_end_matches('\n');
+            match change.tag() {
+                ChangeTag::Delete => write_line(out, &format!("-{}", line), color, |s| s.red())?,
+                ChangeTag::Insert => write_line(out, &format!("+{}", line), color, |s| s.green())?,
+                ChangeTag::Equal => writeln!(out, " {}", line)?,
+            }
+            if change.missing_newline() {
+                writeln!(out, "\\ No newline at end of file")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `line` to `out`, styled with `style` if `color` is set.
+fn write_line<S: fmt::Display>(
+    out: &mut impl Write,
+    line: &str,
+    color: bool,
+    style: impl Fn(&str) -> S,
+) -> io::Result<()> {
+    if color {
+        writeln!(out, "{}", style(line))
+    } else {
+        writeln!(out, "{}", line)
+    }
+}
+
+/// The diff from `old` to `new` without color, as lines.
+#[cfg(test)]
+fn diff_lines(old: &str, new: &str, context: usize) -> Vec<String> {
+    let mut out = Vec::new();
+    write_diff(&mut out, "a.txt", "b.txt", old, new, context, false).unwrap();
+    String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn test_write_diff() {
+    let old: String = (1..=12).map(|n| format!("line {}\n", n)).collect();
+    let new = old.replace("line 6\n", "line six\n");
+    assert_eq!(
+        diff_lines(&old, &new, DEFAULT_CONTEXT),
+        [
+            "--- a.txt",
+            "+++ b.txt",
+            "@@ -3,7 +3,7 @@",
+            " line 3",
+            " line 4",
+            " line 5",
+            "-line 6",
+            "+line six",
+            " line 7",
+            " line 8",
+            " line 9",
+        ]
+    );
+    assert_eq!(
+        diff_lines(&old, &new, 0),
+        [
+            "--- a.txt",
+            "+++ b.txt",
+            "@@ -6 +6 @@",
+            "-line 6",
+            "+line six"
+        ]
+    );
+    assert!(diff_lines(&old, &old, DEFAULT_CONTEXT).is_empty());
+}
+
+#[test]
+fn test_write_diff_missing_newline() {
+    assert_eq!(
+        diff_lines("one\ntwo", "one\n2", 1),
+        [
+            "--- a.txt",
+            "+++ b.txt",
+            "@@ -1,2 +1,2 @@",
+            " one",
+            "-two",
+            "\\ No newline at end of file",
+            "+2",
+            "\\ No newline at end of file",
+        ]
+    );
+    // Adding the newline is a change of its own.
+    assert_eq!(
+        diff_lines("one", "one\n", 1),
+        [
+            "--- a.txt",
+            "+++ b.txt",
+            "@@ -1 +1 @@",
+            "-one",
+            "\\ No newline at end of file",
+            "+one",
+        ]
+    );
+}
@@ -1,52 +1,103 @@
-use regex::Regex;
+use pattern::Pattern;
 use std::env;
-use std::fs;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
 use text_colorizer::*;
 
+mod diff;
+mod pattern;
+mod walk;
+
 #[derive(Debug)]
 struct Arguments {
     target: String,
     replacement: String,
-    filename: String,
-    output: String,
+    /// The files and directories to work on; just FILENAME unless in place.
+    paths: Vec<String>,
+    /// Where to write the result, unless replacing in place.
+    output: Option<String>,
+    backup: Option<String>,
+    preserve_mtime: bool,
+    filters: walk::Filters,
+    /// Take TARGET and REPLACEMENT exactly as written, not as a regex.
+    fixed_strings: bool,
+    /// Show a diff of each change instead of making it, with this many
+    /// lines of context.
+    dry_run: Option<usize>,
+}
+
+/// What happened to one file replaced in place.
+enum Outcome {
+    Replaced(usize),
+    /// In a dry run: the number of matches, and the diff to show for them.
+    Previewed(usize, Vec<u8>),
+    Unchanged,
+    Binary,
+    Failed(io::Error),
 }
 
 fn main() {
     let args = parse_args();
 
+    let pattern = match Pattern::new(&args.target, args.fixed_strings) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            eprintln!("{} failed to replace text: {:?}", "Error:".red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let output = match &args.output {
+        Some(output) => output,
+        None => return replace_in_place(&args, &pattern),
+    };
+    let filename = &args.paths[0];
     println!(
         "Replacing '{}' with '{}' in file '{}' and writing to '{}'",
-        args.target, args.replacement, args.filename, args.output
+        args.target, args.replacement, filename, output
     );
 
-    let data = match fs::read_to_string(&args.filename) {
+    let data = match fs::read_to_string(filename) {
         Ok(v) => v,
         Err(e) => {
             eprintln!(
                 "{} failed to read from file '{}': {:?}",
                 "Error:".red().bold(),
-                args.filename,
+                filename,
                 e
             );
             std::process::exit(1);
         }
     };
 
-    let replaced_data = match replace(&args.target, &args.replacement, &data) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("{} failed to replace text: {:?}", "Error:".red().bold(), e);
+    let (replaced_data, count) = pattern.replace(&args.replacement, &data);
+
+    if let Some(context) = args.dry_run {
+        let shown = diff::write_diff(
+            &mut io::stdout(),
+            filename,
+            output,
+            &data,
+            &replaced_data,
+            context,
+            io::stdout().is_terminal(),
+        );
+        if let Err(e) = shown {
+            eprintln!("{} failed to show diff: {:?}", "Error:".red().bold(), e);
             std::process::exit(1);
         }
-    };
+        println!("{} matches; nothing written", count);
+        return;
+    }
 
-    match fs::write(&args.output, &replaced_data) {
+    match fs::write(output, &replaced_data) {
         Ok(_) => {}
         Err(e) => {
             eprintln!(
                 "{} failed to write to file '{}': {:?}",
                 "Error:".red().bold(),
-                args.filename,
+                output,
                 e
             );
             std::process::exit(1);
@@ -54,39 +105,491 @@ fn main() {
     };
 }
 
+/// Replace matches of `pattern` in place in every file `args` selects, and print
+/// what happened to each.
+fn replace_in_place(args: &Arguments, pattern: &Pattern) {
+    let quoted: Vec<String> = args.paths.iter().map(|p| format!("'{}'", p)).collect();
+    println!(
+        "Replacing '{}' with '{}' in {} in place",
+        args.target,
+        args.replacement,
+        quoted.join(", ")
+    );
+
+    let found = match walk::find_files(&args.paths, &args.filters) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!(
+                "{} bad --include or --exclude: {}",
+                "Error:".red().bold(),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+    for e in &found.errors {
+        eprintln!("{} {}", "Warning:".yellow().bold(), e);
+    }
+
+    let (mut replacements, mut changed, mut binary, mut failed) = (0, 0, 0, 0);
+    for file in &found.files {
+        match replace_file(file, pattern, args) {
+            Outcome::Replaced(count) => {
+                println!("  {}: {} replaced", file.display(), count);
+                replacements += count;
+                changed += 1;
+            }
+            Outcome::Previewed(count, diff) => {
+                println!("  {}: {} to replace", file.display(), count);
+                // The summary at the end says if anything couldn't be shown.
+                let _ = io::stdout().write_all(&diff);
+                replacements += count;
+                changed += 1;
+            }
+            Outcome::Unchanged => {}
+            Outcome::Binary => {
+                println!("  {}: skipped, binary", file.display());
+                binary += 1;
+            }
+            Outcome::Failed(e) => {
+                println!("  {}: {} {}", file.display(), "failed:".red().bold(), e);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} {} in {} of {} files; skipped {} binary, {} failed",
+        replacements,
+        match args.dry_run {
+            Some(_) => "to replace",
+            None => "replaced",
+        },
+        changed,
+        found.files.len(),
+        binary,
+        failed
+    );
+    if args.dry_run.is_some() {
+        println!("dry run: nothing written");
+    }
+    if failed > 0 || !found.errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Replace matches of `pattern` in the file at `path`, leaving it alone if it
+/// looks binary or has nothing to replace.
+fn replace_file(path: &Path, pattern: &Pattern, args: &Arguments) -> Outcome {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Outcome::Failed(e),
+    };
+    // Text files don't hold NUL bytes, and these edits are for UTF-8 text.
+    if bytes.contains(&0) {
+        return Outcome::Binary;
+    }
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return Outcome::Binary,
+    };
+
+    let (replaced, count) = pattern.replace(&args.replacement, &text);
+    if count == 0 {
+        return Outcome::Unchanged;
+    }
+    if let Some(context) = args.dry_run {
+        let name = path.to_string_lossy();
+        let mut shown = Vec::new();
+        return match diff::write_diff(
+            &mut shown,
+            &name,
+            &name,
+            &text,
+            &replaced,
+            context,
+            io::stdout().is_terminal(),
+        ) {
+            Ok(()) => Outcome::Previewed(count, shown),
+            Err(e) => Outcome::Failed(e),
+        };
+    }
+    match write_in_place(path, &replaced, args.backup.as_deref(), args.preserve_mtime) {
+        Ok(()) => Outcome::Replaced(count),
+        Err(e) => Outcome::Failed(e),
+    }
+}
+
 fn print_usage() {
     eprintln!(
-        "{} - change all occurrences of a string in a file",
+        "{} - change all occurrences of a string in files",
+        "quickreplace".green()
+    );
+    eprintln!(
+        "Usage: {} [OPTIONS] TARGET REPLACEMENT FILENAME OUTPUT",
         "quickreplace".green()
     );
     eprintln!(
-        "Usage: {} TARGET REPLACEMENT FILENAME OUTPUT",
+        "       {} --in-place [OPTIONS] TARGET REPLACEMENT PATH...",
         "quickreplace".green()
     );
+    eprintln!("Options:");
+    eprintln!("  --in-place         change files where they are; OUTPUT = FILENAME does too");
+    eprintln!("  --backup SUFFIX    keep each original, with SUFFIX added to its name");
+    eprintln!("  --preserve-mtime   keep each file's modification time");
+    eprintln!("  --include GLOB     in directories, change only files matching GLOB");
+    eprintln!("  --exclude GLOB     in directories, skip files matching GLOB");
+    eprintln!("  --no-ignore        don't skip hidden files or those .gitignore lists");
+    eprintln!("  --fixed-strings    match TARGET and insert REPLACEMENT exactly as written");
+    eprintln!("  --dry-run, --diff  write nothing; show a diff of what would change");
+    eprintln!(
+        "  --context N        lines of context around each change (default {})",
+        diff::DEFAULT_CONTEXT
+    );
+    eprintln!("Directories are searched recursively. Binary files are skipped.");
+}
+
+/// Print the usage message and `message`, and exit.
+fn usage_error(message: &str) -> ! {
+    print_usage();
+    eprintln!("{}: {}", "Error".red().bold(), message);
+    std::process::exit(1);
 }
 
 fn parse_args() -> Arguments {
-    let args: Vec<String> = env::args().skip(1).collect();
+    let mut in_place = false;
+    let mut backup = None;
+    let mut preserve_mtime = false;
+    let mut filters = walk::Filters::default();
+    let mut fixed_strings = false;
+    let mut dry_run = false;
+    let mut context = None;
+    let mut positional = Vec::new();
 
-    if args.len() != 4 {
-        print_usage();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--in-place" => in_place = true,
+            "--preserve-mtime" => preserve_mtime = true,
+            "--no-ignore" => filters.no_ignore = true,
+            "--fixed-strings" => fixed_strings = true,
+            "--dry-run" | "--diff" => dry_run = true,
+            "--backup" | "--include" | "--exclude" | "--context" => {
+                let value = match args.next() {
+                    Some(value) if !value.is_empty() => value,
+                    _ => usage_error(&format!("{} needs a value", arg)),
+                };
+                match arg.as_str() {
+                    "--backup" => backup = Some(value),
+                    "--include" => filters.include.push(value),
+                    "--exclude" => filters.exclude.push(value),
+                    _ => match value.parse() {
+                        Ok(lines) => context = Some(lines),
+                        Err(_) => usage_error(&format!("bad --context '{}'", value)),
+                    },
+                }
+            }
+            _ if arg.starts_with("--") => usage_error(&format!("unknown option '{}'", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    if context.is_some() && !dry_run {
+        usage_error("--context only applies to --dry-run");
+    }
+
+    // Writing the output over the input can only be done safely in place.
+    if !in_place && positional.len() == 4 && positional[2] == positional[3] {
+        in_place = true;
+        positional.pop();
+    }
+    if in_place {
+        if positional.len() < 3 {
+            usage_error(&format!(
+                "expected at least 3 arguments, got {}",
+                positional.len()
+            ));
+        }
+    } else {
+        if positional.len() != 4 {
+            usage_error(&format!("expected 4 arguments, got {}", positional.len()));
+        }
+        if backup.is_some()
+            || preserve_mtime
+            || filters.no_ignore
+            || !filters.include.is_empty()
+            || !filters.exclude.is_empty()
+        {
+            usage_error("options other than --in-place only apply to replacing in place");
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let target = positional.next().unwrap();
+    let replacement = positional.next().unwrap();
+    if fixed_strings && target.is_empty() {
+        usage_error("TARGET can't be empty with --fixed-strings");
+    }
+    let mut paths: Vec<String> = positional.collect();
+    let output = if in_place { None } else { paths.pop() };
+    // Don't go on to rewrite the backups an earlier run left.
+    filters.skip_suffix = backup.clone();
+
+    Arguments {
+        target,
+        replacement,
+        paths,
+        output,
+        backup,
+        preserve_mtime,
+        filters,
+        fixed_strings,
+        dry_run: dry_run.then(|| context.unwrap_or(diff::DEFAULT_CONTEXT)),
+    }
+}
+
+/// Replace the contents of the file at `path` with `data`, so that a crash
+/// leaves either the old file or the new one, never part of each.
+///
+/// The data goes to a temporary file in the same directory, which is flushed
+/// to disk and then renamed over the original. With `backup`, the original is
+/// kept under its name with `backup` added. The new file gets the original's
+/// permissions, and with `preserve_mtime`, its modification time as well. A
+/// symbolic link is followed, and the file it points to replaced.
+fn write_in_place(
+    path: &Path,
+    data: &str,
+    backup: Option<&str>,
+    preserve_mtime: bool,
+) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let metadata = fs::metadata(&path)?;
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy()),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file")),
+    };
+    let temp_path = dir.join(format!(".{}.quickreplace-{}", name, std::process::id()));
+
+    write_temp(&temp_path, data, &metadata, preserve_mtime)?;
+    let renamed = match backup {
+        Some(suffix) => keep_backup(&path, &dir.join(format!("{}{}", name, suffix))),
+        None => Ok(()),
+    }
+    .and_then(|_| fs::rename(&temp_path, &path));
+    if let Err(e) = renamed {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    // Make the rename itself durable. The file has been replaced either way,
+    // so failing to is only worth a warning.
+    #[cfg(unix)]
+    if let Err(e) = File::open(dir).and_then(|dir| dir.sync_all()) {
         eprintln!(
-            "{}: expected 4 arguments, got {}",
-            "Error".red().bold(),
-            args.len()
+            "{} replaced '{}', but couldn't flush its directory to disk: {}",
+            "Warning:".yellow().bold(),
+            path.display(),
+            e
         );
-        std::process::exit(1);
     }
+    Ok(())
+}
 
-    Arguments {
-        target: args[0].clone(),
-        replacement: args[1].clone(),
-        filename: args[2].clone(),
-        output: args[3].clone(),
+/// Create the file `temp_path` holding `data`, with the permissions, and
+/// optionally the modification time, in `metadata`, and flush it to disk.
+/// Fails without touching it if `temp_path` already exists, and removes it
+/// again if anything goes wrong after creating it.
+fn write_temp(
+    temp_path: &Path,
+    data: &str,
+    metadata: &fs::Metadata,
+    preserve_mtime: bool,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+    let written = file
+        .write_all(data.as_bytes())
+        .and_then(|_| file.set_permissions(metadata.permissions()))
+        .and_then(|_| {
+            if preserve_mtime {
+                file.set_modified(metadata.modified()?)?;
+            }
+            Ok(())
+        })
+        .and_then(|_| file.sync_all());
+    if written.is_err() {
+        let _ = fs::remove_file(temp_path);
+    }
+    written
+}
+
+/// Keep the file at `path` as `backup_path` too, replacing any earlier
+/// backup. A hard link keeps it exactly as it was; where links aren't
+/// supported, it is copied.
+fn keep_backup(path: &Path, backup_path: &Path) -> io::Result<()> {
+    match fs::remove_file(backup_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    if fs::hard_link(path, backup_path).is_err() {
+        fs::copy(path, backup_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("quickreplace-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The names of the entries in `dir`, sorted.
+#[cfg(test)]
+fn entries(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_write_in_place() {
+    let dir = scratch_dir("write-in-place");
+    let path = dir.join("notes.txt");
+    fs::write(&path, "old").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    write_in_place(&path, "new", None, false).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    // The temporary file was renamed into place, not left behind.
+    assert_eq!(entries(&dir), ["notes.txt"]);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
     }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_in_place_backup() {
+    let dir = scratch_dir("backup");
+    let path = dir.join("notes.txt");
+    fs::write(&path, "first").unwrap();
+
+    write_in_place(&path, "second", Some(".bak"), false).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+    assert_eq!(
+        fs::read_to_string(dir.join("notes.txt.bak")).unwrap(),
+        "first"
+    );
+
+    // A later backup replaces the earlier one.
+    write_in_place(&path, "third", Some(".bak"), false).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "third");
+    assert_eq!(
+        fs::read_to_string(dir.join("notes.txt.bak")).unwrap(),
+        "second"
+    );
+    assert_eq!(entries(&dir), ["notes.txt", "notes.txt.bak"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_in_place_mtime() {
+    let dir = scratch_dir("mtime");
+    let path = dir.join("notes.txt");
+    fs::write(&path, "old").unwrap();
+    let then = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(then)
+        .unwrap();
+
+    write_in_place(&path, "new", None, true).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), then);
+    write_in_place(&path, "newer", None, false).unwrap();
+    assert!(fs::metadata(&path).unwrap().modified().unwrap() > then);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_write_in_place_temp_collision() {
+    let dir = scratch_dir("collision");
+    let path = dir.join("notes.txt");
+    fs::write(&path, "old").unwrap();
+    let temp_path = dir.join(format!(".notes.txt.quickreplace-{}", std::process::id()));
+    fs::write(&temp_path, "someone else's").unwrap();
+
+    // Neither the original nor the file in the way is touched.
+    let error = write_in_place(&path, "new", Some(".bak"), false).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+    assert_eq!(fs::read_to_string(&temp_path).unwrap(), "someone else's");
+    assert!(!dir.join("notes.txt.bak").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
 }
 
-fn replace(target: &str, replacement: &str, text: &str) -> Result<String, regex::Error> {
-    let re = Regex::new(target)?;
-    Ok(re.replace_all(text, replacement).to_string())
+#[test]
+fn test_replace_file_skips_binary() {
+    let dir = scratch_dir("binary");
+    let args = Arguments {
+        target: "old".to_string(),
+        replacement: "new".to_string(),
+        paths: Vec::new(),
+        output: None,
+        backup: None,
+        preserve_mtime: false,
+        filters: walk::Filters::default(),
+        fixed_strings: true,
+        dry_run: None,
+    };
+    let pattern = Pattern::new(&args.target, args.fixed_strings).unwrap();
+
+    let nul = dir.join("nul.dat");
+    fs::write(&nul, b"old\0old").unwrap();
+    let latin1 = dir.join("latin1.txt");
+    fs::write(&latin1, b"old caf\xe9").unwrap();
+    let text = dir.join("text.txt");
+    fs::write(&text, "old and old").unwrap();
+    let other = dir.join("other.txt");
+    fs::write(&other, "nothing to see").unwrap();
+
+    assert!(matches!(
+        replace_file(&nul, &pattern, &args),
+        Outcome::Binary
+    ));
+    assert!(matches!(
+        replace_file(&latin1, &pattern, &args),
+        Outcome::Binary
+    ));
+    assert!(matches!(
+        replace_file(&text, &pattern, &args),
+        Outcome::Replaced(2)
+    ));
+    assert!(matches!(
+        replace_file(&other, &pattern, &args),
+        Outcome::Unchanged
+    ));
+    assert_eq!(fs::read(&nul).unwrap(), b"old\0old");
+    assert_eq!(fs::read(&latin1).unwrap(), b"old caf\xe9");
+    assert_eq!(fs::read_to_string(&text).unwrap(), "new and new");
+
+    fs::remove_dir_all(&dir).unwrap();
 }
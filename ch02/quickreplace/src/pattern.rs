@@ -0,0 +1,107 @@
+//! What to look for: a regular expression, or with `--fixed-strings`, a
+//! string taken exactly as written.
+//!
+//! A fixed string needs no escaping: `a.b(c)` matches only `a.b(c)`, and a
+//! `$` in its replacement is just a dollar sign rather than a reference to a
+//! capture group. Fixed strings are found with `memchr`'s substring search,
+//! which doesn't need a regex to be compiled at all.
+
+use memchr::memmem;
+use regex::Regex;
+
+pub enum Pattern {
+    Regex(Regex),
+    // A `Finder` is large, and there is only ever one pattern.
+    Literal(Box<memmem::Finder<'static>>),
+}
+
+impl Pattern {
+    /// The pattern for `target`: a regular expression, unless `fixed`.
+    pub fn new(target: &str, fixed: bool) -> Result<Pattern, regex::Error> {
+        if fixed {
+            Ok(Pattern::Literal(Box::new(
+                memmem::Finder::new(target).into_owned(),
+            )))
+        } else {
+            Regex::new(target).map(Pattern::Regex)
+        }
+    }
+
+    /// Replace every match in `text` with `replacement`, returning the new
+    /// text and how many matches were replaced. For a regular expression,
+    /// `$1`, `$name` and the like in `replacement` stand for the groups it
+    /// captured; for a fixed string, `replacement` is used as it is.
+    pub fn replace(&self, replacement: &str, text: &str) -> (String, usize) {
+        match self {
+            Pattern::Regex(re) => {
+                let count = re.find_iter(text).count();
+                (re.replace_all(text, replacement).to_string(), count)
+            }
+            Pattern::Literal(finder) => {
+                let needle_len = finder.needle().len();
+                let mut replaced = String::with_capacity(text.len());
+                let mut count = 0;
+                let mut last = 0;
+                // Matches don't overlap: `find_iter` resumes after each one.
+                for start in finder.find_iter(text.as_bytes()) {
+                    replaced.push_str(&text[last..start]);
+                    replaced.push_str(replacement);
+                    last = start + needle_len;
+                    count += 1;
+                }
+                replaced.push_str(&text[last..]);
+                (replaced, count)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_literal_metacharacters() {
+    let pattern = Pattern::new("a.b(c)", true).unwrap();
+    assert_eq!(
+        pattern.replace("x", "a.b(c) axb(c) a.b(c)"),
+        ("x axb(c) x".to_string(), 2)
+    );
+
+    let pattern = Pattern::new(r"^[*+?]{1,2}|\d$", true).unwrap();
+    assert_eq!(
+        pattern.replace("<>", r"^[*+?]{1,2}|\d$ and ^[*+?]{1,2}|\d"),
+        (r"<> and ^[*+?]{1,2}|\d".to_string(), 1)
+    );
+
+    // The same target as a regular expression means something else entirely.
+    let pattern = Pattern::new("a.b(c)", false).unwrap();
+    assert_eq!(
+        pattern.replace("x", "a.b(c) axbc"),
+        ("a.b(c) x".to_string(), 1)
+    );
+    assert!(Pattern::new("(unclosed", false).is_err());
+    assert!(Pattern::new("(unclosed", true).is_ok());
+}
+
+#[test]
+fn test_literal_replacement() {
+    // `$` in the replacement is kept, not read as a capture group.
+    let pattern = Pattern::new("price", true).unwrap();
+    assert_eq!(
+        pattern.replace("$1.00 ${name} $$", "price: price"),
+        ("$1.00 ${name} $$: $1.00 ${name} $$".to_string(), 2)
+    );
+    let pattern = Pattern::new("(price)", false).unwrap();
+    assert_eq!(pattern.replace("[$1]", "price"), ("[price]".to_string(), 1));
+
+    // Matches are found left to right and never overlap.
+    let pattern = Pattern::new("aa", true).unwrap();
+    assert_eq!(pattern.replace("b", "aaaaa"), ("bba".to_string(), 2));
+    let pattern = Pattern::new("é.", true).unwrap();
+    assert_eq!(
+        pattern.replace("e", "café. café"),
+        ("cafe café".to_string(), 1)
+    );
+    let pattern = Pattern::new("missing", true).unwrap();
+    assert_eq!(
+        pattern.replace("x", "nothing here"),
+        ("nothing here".to_string(), 0)
+    );
+}
@@ -0,0 +1,232 @@
+//! Finding the files to work on: every file named on the command line, and
+//! every file under the directories named there.
+//!
+//! Directories are walked the way `git` would see them, skipping hidden
+//! files and anything matched by `.gitignore`, `.ignore` or the global git
+//! excludes, unless those rules are turned off. `--include` and `--exclude`
+//! globs narrow the walk further, matched against paths relative to the
+//! directory being walked; a file named outright is always taken. Nothing
+//! inside a `.git` directory is ever touched, and neither are backups an
+//! earlier run with the same `--backup` left behind.
+
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Which files under a directory to take.
+#[derive(Debug, Default)]
+pub struct Filters {
+    /// Take only files matching one of these globs, if there are any.
+    pub include: Vec<String>,
+    /// Never take files matching these globs.
+    pub exclude: Vec<String>,
+    /// Take hidden and ignored files too.
+    pub no_ignore: bool,
+    /// Never take files whose names end with this, such as backups.
+    pub skip_suffix: Option<String>,
+}
+
+/// The files found, in order, and anything that went wrong along the way.
+pub struct Found {
+    pub files: Vec<PathBuf>,
+    pub errors: Vec<ignore::Error>,
+}
+
+/// Find the files in or under `paths` that `filters` let through, each once.
+/// Fails only if a glob is malformed.
+pub fn find_files(paths: &[String], filters: &Filters) -> Result<Found, ignore::Error> {
+    let mut found = Found {
+        files: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    for path in paths {
+        // Globs are matched relative to the directory they were given for,
+        // so `src/*.rs` means the same under `.` as under `/home/me/project`.
+        let mut overrides = OverrideBuilder::new(path);
+        for glob in &filters.include {
+            overrides.add(glob)?;
+        }
+        for glob in &filters.exclude {
+            overrides.add(&format!("!{}", glob))?;
+        }
+        let skip_suffix = filters.skip_suffix.clone();
+        let walk = WalkBuilder::new(path)
+            .standard_filters(!filters.no_ignore)
+            .require_git(false)
+            .overrides(overrides.build()?)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |entry| {
+                let name = entry.file_name().to_string_lossy();
+                let skipped = match &skip_suffix {
+                    Some(suffix) => entry.depth() > 0 && name.ends_with(suffix.as_str()),
+                    None => false,
+                };
+                name != ".git" && !skipped
+            })
+            .build();
+        for entry in walk {
+            match entry {
+                Ok(entry) if entry.file_type().is_some_and(|t| t.is_file()) => {
+                    let file = entry.into_path();
+                    // The same file may be reached by more than one path.
+                    let key = file.canonicalize().unwrap_or_else(|_| file.clone());
+                    if seen.insert(key) {
+                        found.files.push(file);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => found.errors.push(e),
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Create `files` under `dir`, each with some text in it.
+#[cfg(test)]
+fn create_files(dir: &std::path::Path, files: &[&str]) {
+    for file in files {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "text").unwrap();
+    }
+}
+
+/// The files `find_files` finds under `dir`, relative to it.
+#[cfg(test)]
+fn found_under(dir: &std::path::Path, filters: &Filters) -> Vec<String> {
+    let found = find_files(&[dir.to_string_lossy().into_owned()], filters).unwrap();
+    assert!(found.errors.is_empty());
+    found
+        .files
+        .iter()
+        .map(|file| {
+            file.strip_prefix(dir)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn test_find_files_ignores() {
+    let dir = crate::scratch_dir("walk-ignores");
+    create_files(
+        &dir,
+        &[
+            "a.rs",
+            "build/out.rs",
+            "notes.log",
+            ".hidden/b.rs",
+            ".env",
+            ".git/config",
+        ],
+    );
+    std::fs::write(dir.join(".gitignore"), "build/\n*.log\n").unwrap();
+
+    assert_eq!(found_under(&dir, &Filters::default()), ["a.rs"]);
+    let everything = Filters {
+        no_ignore: true,
+        ..Filters::default()
+    };
+    assert_eq!(
+        found_under(&dir, &everything),
+        [
+            ".env",
+            ".gitignore",
+            ".hidden/b.rs",
+            "a.rs",
+            "build/out.rs",
+            "notes.log"
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_find_files_globs() {
+    let dir = crate::scratch_dir("walk-globs");
+    create_files(
+        &dir,
+        &["a.rs", "a.txt", "src/b.rs", "src/c.rs", "src/deep/d.rs"],
+    );
+
+    let rust = Filters {
+        include: vec!["*.rs".to_string()],
+        exclude: vec!["c.rs".to_string()],
+        ..Filters::default()
+    };
+    assert_eq!(
+        found_under(&dir, &rust),
+        ["a.rs", "src/b.rs", "src/deep/d.rs"]
+    );
+
+    // Globs with a slash in them are relative to the directory walked, even
+    // when it is given by its absolute path.
+    assert!(dir.is_absolute());
+    let src = Filters {
+        include: vec!["src/*.rs".to_string()],
+        ..Filters::default()
+    };
+    assert_eq!(found_under(&dir, &src), ["src/b.rs", "src/c.rs"]);
+    let not_deep = Filters {
+        exclude: vec!["src/deep".to_string()],
+        ..Filters::default()
+    };
+    assert_eq!(
+        found_under(&dir, &not_deep),
+        ["a.rs", "a.txt", "src/b.rs", "src/c.rs"]
+    );
+
+    assert!(find_files(
+        &[dir.to_string_lossy().into_owned()],
+        &Filters {
+            include: vec!["[".to_string()],
+            ..Filters::default()
+        }
+    )
+    .is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_find_files_skips_backups() {
+    let dir = crate::scratch_dir("walk-backups");
+    create_files(&dir, &["a.rs", "a.rs.bak", "src/b.rs", "src/b.rs.bak"]);
+
+    let filters = Filters {
+        skip_suffix: Some(".bak".to_string()),
+        ..Filters::default()
+    };
+    assert_eq!(found_under(&dir, &filters), ["a.rs", "src/b.rs"]);
+    // Unless one is named outright.
+    let named = dir.join("a.rs.bak").to_string_lossy().into_owned();
+    assert_eq!(
+        find_files(&[named], &filters).unwrap().files,
+        [dir.join("a.rs.bak")]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_find_files_once_each() {
+    let dir = crate::scratch_dir("walk-once");
+    create_files(&dir, &["a.rs", "src/b.rs"]);
+
+    // The same files reached by overlapping paths, and by a roundabout one.
+    let paths = [
+        dir.to_string_lossy().into_owned(),
+        dir.join("src").to_string_lossy().into_owned(),
+        dir.join("src/../a.rs").to_string_lossy().into_owned(),
+    ];
+    let found = find_files(&paths, &Filters::default()).unwrap();
+    assert_eq!(found.files, [dir.join("a.rs"), dir.join("src/b.rs")]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}